@@ -0,0 +1,125 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+//! Times a compound context filter with and without the analysis
+//! shared between its leaves. Run it with
+//!   cargo bench --no-default-features --bench filters
+//! With FILTER_BENCH_SCRAPE set to a scrape file it filters that, and
+//! otherwise it makes up samples shaped like CompCert's.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dataloader::bench_support::*;
+
+const NUM_SYNTHETIC_SAMPLES: usize = 100_000;
+
+/// Applies a filter the way filters worked before TacticAnalysis,
+/// analyzing the sample again for every leaf.
+fn accepts_unshared(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    filter: &ContextFilterAST,
+    scraped: &ScrapedTactic,
+) -> bool {
+    match filter {
+        ContextFilterAST::And(subfilters) => subfilters
+            .iter()
+            .all(|subfilter| accepts_unshared(args, signatures, subfilter, scraped)),
+        ContextFilterAST::Or(subfilters) => subfilters
+            .iter()
+            .any(|subfilter| accepts_unshared(args, signatures, subfilter, scraped)),
+        ContextFilterAST::Not(subfilter) => !accepts_unshared(args, signatures, subfilter, scraped),
+        _ => apply_filter(args, signatures, filter, scraped),
+    }
+}
+
+fn samples() -> Vec<ScrapedTactic> {
+    if let Ok(path) = std::env::var("FILTER_BENCH_SCRAPE") {
+        return scraped_tactics_iter(scraped_from_file(&path, BadLinePolicy::Skip).unwrap())
+            .collect::<Result<_, _>>()
+            .unwrap();
+    }
+    let tactics = [
+        "intros n m H.",
+        "apply H2.",
+        "rewrite <- H1 in H3.",
+        "simpl.",
+        "destruct (Nat.eq_dec n m) as [E|E].",
+        "- auto.",
+        "exact (Plus.plus_comm n m).",
+        "omega.",
+    ];
+    (0..NUM_SYNTHETIC_SAMPLES)
+        .map(|idx| ScrapedTactic {
+            relevant_lemmas: vec!["Plus.plus_comm : forall n m : nat, n + m = m + n".to_string()],
+            prev_tactics: vec![],
+            context: ProofContext {
+                fg_goals: vec![Obligation {
+                    hypotheses: (0..idx % 12)
+                        .map(|hyp| format!("H{0} : f x{0} (g y) = h (x{0} + 1)", hyp))
+                        .collect(),
+                    goal: format!(
+                        "forall n m : nat, n <= m -> f n (g {}) = h (m + {})",
+                        idx % 7,
+                        idx % 5
+                    ),
+                }],
+                bg_goals: vec![],
+                shelved_goals: vec![],
+                given_up_goals: vec![],
+            },
+            tactic: tactics[idx % tactics.len()].to_string(),
+            provenance: None,
+        })
+        .collect()
+}
+
+fn bench_compound_filter(c: &mut Criterion) {
+    let data = samples();
+    let args: DataloaderArgs = Default::default();
+    let signatures = TacticSignatures::default();
+    let filter =
+        parse_filter("(goal-args + hyp-args + rel-lemma-args) % maxargs:1 % default").unwrap();
+    assert!(data.iter().all(|scraped| {
+        accepts_unshared(&args, &signatures, &filter, scraped)
+            == apply_filter(&args, &signatures, &filter, scraped)
+    }));
+
+    let mut group = c.benchmark_group("compound filter");
+    group.sample_size(10);
+    group.bench_function("analysis per leaf", |b| {
+        b.iter(|| {
+            data.iter()
+                .filter(|scraped| accepts_unshared(&args, &signatures, &filter, scraped))
+                .count()
+        })
+    });
+    group.bench_function("shared analysis", |b| {
+        b.iter(|| {
+            data.iter()
+                .filter(|scraped| apply_filter(&args, &signatures, &filter, scraped))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_compound_filter);
+criterion_main!(benches);
@@ -0,0 +1,420 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use std::fmt;
+
+/// One product at the head of a term: a variable bound by forall or
+/// fun, or the premise of an arrow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binder<'a> {
+    /// The bound name, or None for the premise of an arrow, for "_",
+    /// and for pattern binders like '(a, b)
+    pub name: Option<&'a str>,
+    /// The declared type, if there is one
+    pub ty: Option<&'a str>,
+    pub implicit: bool,
+    /// Whether the rest of the term can refer to this binder. Numbered
+    /// intros and destructs only count the products that it can't.
+    pub dependent: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinderError {
+    pub term: String,
+    pub msg: &'static str,
+}
+
+impl fmt::Display for BinderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in term \"{}\"", self.msg, self.term)
+    }
+}
+
+impl std::error::Error for BinderError {}
+
+/// Parses the products at the head of a term, looking through nested
+/// foralls, funs and arrows, like intros would.
+pub fn parse_binders(term: &str) -> Result<Vec<Binder<'_>>, BinderError> {
+    let mut binders = Vec::new();
+    let mut rest = term;
+    loop {
+        let body = strip_outer_parens(rest.trim());
+        let terminator = if starts_with_keyword(body, "forall") {
+            ","
+        } else if starts_with_keyword(body, "fun") {
+            "=>"
+        } else {
+            match find_top_level_arrow(body) {
+                Some(arrow) => {
+                    binders.push(Binder {
+                        name: None,
+                        ty: Some(body[..arrow].trim()),
+                        implicit: false,
+                        dependent: false,
+                    });
+                    rest = &body[arrow + 2..];
+                    continue;
+                }
+                None => return Ok(binders),
+            }
+        };
+        let keyword_len = if terminator == "," { 6 } else { 3 };
+        let after_keyword = &body[keyword_len..];
+        let end = find_top_level(after_keyword, terminator)
+            .ok_or_else(|| binder_error(term, "Unterminated binder list"))?;
+        let group = &after_keyword[..end];
+        rest = &after_keyword[end + terminator.len()..];
+        for (binder, decl_end) in parse_binder_group(term, group)? {
+            // Later binders in the same group can mention earlier ones,
+            // as in forall (n : nat) (H : n > 0), ...
+            let scope_start = offset_in(term, group) + decl_end;
+            let dependent = match binder.name {
+                Some(name) => mentions(&term[scope_start..], name),
+                None => binder.dependent,
+            };
+            binders.push(Binder { dependent, ..binder });
+        }
+    }
+}
+
+/// The name of the premise that "intros until n" stops at, which is
+/// also the one that "induction n" and "destruct n" work on: the nth
+/// product that the rest of the goal doesn't depend on. That premise
+/// has no name if it's the left side of an arrow.
+pub fn numbered_premise(goal: &str, n: usize) -> Result<Option<&str>, BinderError> {
+    if n == 0 {
+        return Err(binder_error(goal, "Premises are numbered from 1"));
+    }
+    parse_binders(goal)?
+        .into_iter()
+        .filter(|binder| !binder.dependent)
+        .nth(n - 1)
+        .map(|binder| binder.name)
+        .ok_or_else(|| binder_error(goal, "Not enough non-dependent premises"))
+}
+
+fn binder_error(term: &str, msg: &'static str) -> BinderError {
+    BinderError {
+        term: term.to_string(),
+        msg,
+    }
+}
+
+fn offset_in(whole: &str, part: &str) -> usize {
+    part.as_ptr() as usize - whole.as_ptr() as usize
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+fn mentions(text: &str, name: &str) -> bool {
+    text.split(|c: char| !is_ident_char(c)).any(|word| word == name)
+}
+
+fn starts_with_keyword(s: &str, keyword: &str) -> bool {
+    s.starts_with(keyword)
+        && !s[keyword.len()..]
+            .chars()
+            .next()
+            .map_or(false, is_ident_char)
+}
+
+fn closer(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '{' => Some('}'),
+        '[' => Some(']'),
+        _ => None,
+    }
+}
+
+/// The index of the bracket closing the one that s starts with.
+fn matching_close(s: &str) -> Option<usize> {
+    let mut stack = Vec::new();
+    for (idx, c) in s.char_indices() {
+        if let Some(close) = closer(c) {
+            stack.push(close);
+        } else if c == ')' || c == '}' || c == ']' {
+            if stack.pop() != Some(c) {
+                return None;
+            }
+            if stack.is_empty() {
+                return Some(idx);
+            }
+        }
+    }
+    None
+}
+
+fn strip_outer_parens(s: &str) -> &str {
+    if s.starts_with('(') && matching_close(s) == Some(s.len() - 1) {
+        strip_outer_parens(s[1..s.len() - 1].trim())
+    } else {
+        s
+    }
+}
+
+/// Finds pat in s outside of any brackets. Returns None if it isn't
+/// there, or if the brackets don't balance before it.
+fn find_top_level(s: &str, pat: &str) -> Option<usize> {
+    let mut depth: usize = 0;
+    for (idx, c) in s.char_indices() {
+        if depth == 0 && s[idx..].starts_with(pat) {
+            return Some(idx);
+        }
+        if closer(c).is_some() {
+            depth += 1;
+        } else if c == ')' || c == '}' || c == ']' {
+            depth = depth.checked_sub(1)?;
+        }
+    }
+    None
+}
+
+/// Finds the first arrow outside of brackets. Binders extend as far
+/// right as they can, so any arrow after a top level forall, fun or
+/// exists belongs to its body, and an arrow inside a match belongs to
+/// one of its branches.
+fn find_top_level_arrow(s: &str) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut prev: Option<char> = None;
+    for (idx, c) in s.char_indices() {
+        let at_word_start = !prev.map_or(false, is_ident_char);
+        if closer(c).is_some() {
+            depth += 1;
+        } else if c == ')' || c == '}' || c == ']' {
+            depth = depth.checked_sub(1)?;
+        } else if at_word_start && starts_with_keyword(&s[idx..], "match") {
+            depth += 1;
+        } else if at_word_start && starts_with_keyword(&s[idx..], "end") {
+            depth = depth.checked_sub(1)?;
+        } else if depth == 0 {
+            if at_word_start
+                && ["forall", "fun", "exists", "let", "fix", "cofix"]
+                    .iter()
+                    .any(|keyword| starts_with_keyword(&s[idx..], keyword))
+            {
+                return None;
+            }
+            if s[idx..].starts_with("->") && prev != Some('<') {
+                return Some(idx);
+            }
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+/// Splits "x y : T" into its names and type.
+fn split_typed_names<'a>(
+    term: &str,
+    decl: &'a str,
+) -> Result<(Vec<&'a str>, Option<&'a str>), BinderError> {
+    let (names, ty) = match find_top_level(decl, ":") {
+        Some(colon) if !decl[colon..].starts_with(":=") => {
+            (&decl[..colon], Some(decl[colon + 1..].trim()))
+        }
+        Some(_) => return Err(binder_error(term, "Let binder in a product")),
+        None => (decl, None),
+    };
+    let names: Vec<&str> = names.split_whitespace().collect();
+    if names.iter().any(|name| !name.chars().all(is_ident_char)) {
+        return Err(binder_error(term, "Malformed binder name"));
+    }
+    Ok((names, ty))
+}
+
+fn named_binder<'a>(name: &'a str, ty: Option<&'a str>, implicit: bool) -> Binder<'a> {
+    Binder {
+        name: if name == "_" { None } else { Some(name) },
+        ty,
+        implicit,
+        dependent: false,
+    }
+}
+
+/// Parses the binders between a forall or fun and its comma or
+/// arrow. Each binder comes with the offset in the group where its
+/// scope starts.
+fn parse_binder_group<'a>(
+    term: &str,
+    group: &'a str,
+) -> Result<Vec<(Binder<'a>, usize)>, BinderError> {
+    let mut binders = Vec::new();
+    let mut idx = 0;
+    while idx < group.len() {
+        let rest = &group[idx..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            idx += c.len_utf8();
+            continue;
+        }
+        match c {
+            '(' | '{' | '[' | '`' => {
+                let (bracketed, generalizing) = if c == '`' {
+                    (&rest[1..], true)
+                } else {
+                    (rest, false)
+                };
+                let close = matching_close(bracketed)
+                    .ok_or_else(|| binder_error(term, "Unbalanced binder brackets"))?;
+                let implicit = !bracketed.starts_with('(');
+                let inner = bracketed[1..close].trim();
+                let decl_end = idx + (rest.len() - bracketed.len()) + close + 1;
+                if generalizing {
+                    // Typeclass binders like `{Eq A} may leave the
+                    // name out entirely.
+                    match split_typed_names(term, inner) {
+                        Ok((names, Some(ty))) => binders.extend(
+                            names
+                                .into_iter()
+                                .map(|name| (named_binder(name, Some(ty), implicit), decl_end)),
+                        ),
+                        _ => binders.push((
+                            Binder {
+                                name: None,
+                                ty: Some(inner),
+                                implicit,
+                                dependent: false,
+                            },
+                            decl_end,
+                        )),
+                    }
+                } else {
+                    let (names, ty) = split_typed_names(term, inner)?;
+                    if names.is_empty() {
+                        return Err(binder_error(term, "Empty binder"));
+                    }
+                    binders.extend(
+                        names
+                            .into_iter()
+                            .map(|name| (named_binder(name, ty, implicit), decl_end)),
+                    );
+                }
+                idx = decl_end;
+            }
+            '\'' => {
+                // A pattern binder like '(a, b) or 'pair. It stands
+                // for a single product, which the pattern variables
+                // in the body depend on.
+                let pattern = &rest[1..];
+                let len = if pattern.starts_with('(') {
+                    matching_close(pattern)
+                        .ok_or_else(|| binder_error(term, "Unbalanced pattern binder"))?
+                        + 1
+                } else {
+                    pattern
+                        .find(|c: char| !is_ident_char(c))
+                        .unwrap_or(pattern.len())
+                };
+                if len == 0 {
+                    return Err(binder_error(term, "Empty pattern binder"));
+                }
+                idx += 1 + len;
+                binders.push((
+                    Binder {
+                        name: None,
+                        ty: None,
+                        implicit: false,
+                        dependent: true,
+                    },
+                    idx,
+                ));
+            }
+            _ => {
+                let shares_type = match find_top_level(rest, ":") {
+                    Some(colon) => rest[..colon]
+                        .chars()
+                        .all(|c| is_ident_char(c) || c.is_whitespace()),
+                    None => false,
+                };
+                if shares_type {
+                    // Unbracketed names with a type, which has to be
+                    // the last thing in the group: forall x y : nat, ...
+                    let (names, ty) = split_typed_names(term, rest)?;
+                    binders.extend(
+                        names
+                            .into_iter()
+                            .map(|name| (named_binder(name, ty, false), group.len())),
+                    );
+                    idx = group.len();
+                } else {
+                    let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+                    if len == 0 {
+                        return Err(binder_error(term, "Malformed binder name"));
+                    }
+                    idx += len;
+                    binders.push((named_binder(&rest[..len], None, false), idx));
+                }
+            }
+        }
+    }
+    Ok(binders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(term: &str) -> Vec<(Option<&str>, bool)> {
+        parse_binders(term)
+            .unwrap()
+            .into_iter()
+            .map(|binder| (binder.name, binder.dependent))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_binders() {
+        assert_eq!(
+            summary("forall {A : Type} (x y : A), x = y -> y = x"),
+            vec![
+                (Some("A"), true),
+                (Some("x"), true),
+                (Some("y"), true),
+                (None, false)
+            ]
+        );
+        assert_eq!(
+            summary("forall n (H : n > 0), P n"),
+            vec![(Some("n"), true), (Some("H"), false)]
+        );
+        assert_eq!(
+            summary("A -> forall x : nat, (forall y, R x y) -> Q x"),
+            vec![(None, false), (Some("x"), true), (None, false)]
+        );
+        assert_eq!(
+            summary("forall '(a, b), a = b -> P"),
+            vec![(None, true), (None, false)]
+        );
+        assert_eq!(summary("forall x, x = x <-> True"), vec![(Some("x"), true)]);
+        assert_eq!(
+            summary("P /\\ forall x, Q x -> R"),
+            Vec::<(Option<&str>, bool)>::new()
+        );
+        assert!(parse_binders("forall (x : nat, P x").is_err());
+
+        assert_eq!(numbered_premise("forall n (H : n > 0), P n", 1), Ok(Some("H")));
+        assert_eq!(numbered_premise("forall x y, x = y -> y = x", 1), Ok(None));
+        assert!(numbered_premise("forall x y, x = y", 1).is_err());
+    }
+}
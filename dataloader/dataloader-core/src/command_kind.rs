@@ -0,0 +1,154 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use crate::coq_lexer::{lex_code, LexemeKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofEndKind {
+    Qed,
+    Defined,
+    Admitted,
+    Abort,
+    Save,
+}
+
+impl ProofEndKind {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "Qed" => Some(ProofEndKind::Qed),
+            "Defined" => Some(ProofEndKind::Defined),
+            "Admitted" => Some(ProofEndKind::Admitted),
+            "Abort" => Some(ProofEndKind::Abort),
+            "Save" => Some(ProofEndKind::Save),
+            _ => None,
+        }
+    }
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ProofEndKind::Qed => "Qed",
+            ProofEndKind::Defined => "Defined",
+            ProofEndKind::Admitted => "Admitted",
+            ProofEndKind::Abort => "Abort",
+            ProofEndKind::Save => "Save",
+        }
+    }
+}
+
+/// What a scraped command does to the structure of a proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandKind {
+    /// Anything that isn't one of the below
+    Tactic,
+    /// A bullet, possibly followed by a tactic on the same line.
+    /// Bullets are numbered in the order -, +, *, --, ++, **, ..., so
+    /// two bullets are the same exactly when their numbers are.
+    Bullet(usize),
+    OpenBrace,
+    CloseBrace,
+    /// A numbered goal selector, like "2:", and the kind of the command
+    /// it's applied to
+    GoalSelector(usize, Box<CommandKind>),
+    ProofStart,
+    ProofEnd(ProofEndKind),
+}
+
+const BULLET_CHARS: &str = "-+*";
+
+fn bullet_level(command: &str) -> Option<usize> {
+    let bullet_char = command.chars().next().filter(|c| BULLET_CHARS.contains(*c))?;
+    let count = command.chars().take_while(|c| *c == bullet_char).count();
+    Some((count - 1) * BULLET_CHARS.len() + BULLET_CHARS.find(bullet_char).unwrap())
+}
+
+/// Classifies a scraped command, ignoring its comments.
+pub fn command_kind(command: &str) -> CommandKind {
+    let lexemes = lex_code(command);
+    let first = match lexemes.first() {
+        Some(first) => first,
+        None => return CommandKind::Tactic,
+    };
+    let from_first = &command[first.start..];
+    if let Some(level) = bullet_level(from_first) {
+        return CommandKind::Bullet(level);
+    }
+    match (first.kind, first.text, lexemes.get(1).map(|second| second.text)) {
+        (LexemeKind::Symbol, "{", _) => CommandKind::OpenBrace,
+        (LexemeKind::Symbol, "}", _) => CommandKind::CloseBrace,
+        (LexemeKind::Numeral, num, Some(":")) => match num.replace('_', "").parse() {
+            Ok(goal_num) => CommandKind::GoalSelector(
+                goal_num,
+                Box::new(command_kind(&command[lexemes[1].end..])),
+            ),
+            Err(_) => CommandKind::Tactic,
+        },
+        (LexemeKind::Ident, "Proof", _) => CommandKind::ProofStart,
+        (LexemeKind::Ident, keyword, _) => match ProofEndKind::from_keyword(keyword) {
+            Some(end_kind) => CommandKind::ProofEnd(end_kind),
+            None => CommandKind::Tactic,
+        },
+        _ => CommandKind::Tactic,
+    }
+}
+
+/// In python, command kinds are tuples of a name and its fields, like
+/// ("bullet", 0) or ("goal_selector", 2, ("tactic",)).
+impl IntoPy<PyObject> for CommandKind {
+    fn into_py(self, py: Python) -> PyObject {
+        match self {
+            CommandKind::Tactic => ("tactic",).into_py(py),
+            CommandKind::Bullet(level) => ("bullet", level).into_py(py),
+            CommandKind::OpenBrace => ("open_brace",).into_py(py),
+            CommandKind::CloseBrace => ("close_brace",).into_py(py),
+            CommandKind::GoalSelector(goal_num, inner) => {
+                let inner: PyObject = inner.into_py(py);
+                PyTuple::new(py, &["goal_selector".into_py(py), goal_num.into_py(py), inner])
+                    .into_py(py)
+            }
+            CommandKind::ProofStart => ("proof_start",).into_py(py),
+            CommandKind::ProofEnd(end_kind) => ("proof_end", end_kind.keyword()).into_py(py),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_kind() {
+        use CommandKind::*;
+        assert_eq!(command_kind("  auto."), Tactic);
+        assert_eq!(command_kind("apply Proof_irrelevance."), Tactic);
+        assert_eq!(command_kind("- "), Bullet(0));
+        assert_eq!(command_kind("* auto."), Bullet(2));
+        assert_eq!(command_kind("++"), Bullet(4));
+        assert_eq!(command_kind("(* first *) {"), OpenBrace);
+        assert_eq!(command_kind("}"), CloseBrace);
+        assert_eq!(command_kind("2: auto."), GoalSelector(2, Box::new(Tactic)));
+        assert_eq!(command_kind("1 : {"), GoalSelector(1, Box::new(OpenBrace)));
+        assert_eq!(command_kind("Proof with auto."), ProofStart);
+        assert_eq!(command_kind("Defined."), ProofEnd(ProofEndKind::Defined));
+        assert_eq!(command_kind(""), Tactic);
+    }
+}
@@ -0,0 +1,128 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Plain,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_extension(path: &str) -> Compression {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::Plain,
+        }
+    }
+    pub fn from_magic(header: &[u8]) -> Option<Compression> {
+        if header.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Opens a file for reading, decompressing it if it's gzip or zstd
+/// compressed. The format is taken from the first bytes of the file
+/// when they're recognizable, and from the extension otherwise.
+pub fn open_maybe_compressed(path: &str) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = {
+        let header = reader.fill_buf()?;
+        match Compression::from_magic(header) {
+            Some(compression) => compression,
+            // An empty file has no header, so fall back on the
+            // extension instead of guessing it's plain text.
+            None if header.is_empty() => Compression::from_extension(path),
+            None => Compression::Plain,
+        }
+    };
+    Ok(match compression {
+        Compression::Plain => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    })
+}
+
+/// A file writer which compresses according to the file extension.
+/// Compressed streams have to be terminated, so call finish when
+/// you're done writing instead of just dropping it.
+pub enum MaybeCompressedWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::stream::write::Encoder<'static, BufWriter<File>>),
+}
+
+impl MaybeCompressedWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match Compression::from_extension(path) {
+            Compression::Plain => MaybeCompressedWriter::Plain(file),
+            Compression::Gzip => {
+                MaybeCompressedWriter::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            Compression::Zstd => {
+                MaybeCompressedWriter::Zstd(zstd::stream::write::Encoder::new(file, 0)?)
+            }
+        })
+    }
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            MaybeCompressedWriter::Plain(file) => file,
+            MaybeCompressedWriter::Gzip(encoder) => encoder.finish()?,
+            MaybeCompressedWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for MaybeCompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MaybeCompressedWriter::Plain(w) => w.write(buf),
+            MaybeCompressedWriter::Gzip(w) => w.write(buf),
+            MaybeCompressedWriter::Zstd(w) => w.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MaybeCompressedWriter::Plain(w) => w.flush(),
+            MaybeCompressedWriter::Gzip(w) => w.flush(),
+            MaybeCompressedWriter::Zstd(w) => w.flush(),
+        }
+    }
+}
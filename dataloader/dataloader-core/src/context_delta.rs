@@ -0,0 +1,193 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+use crate::hypothesis::parse_hypothesis;
+use crate::scraped_data::*;
+
+/// What a tactic changed in the proof context. Hypotheses are compared
+/// in the focused obligation, by name, so a hypothesis whose type was
+/// rewritten shows up as modified rather than as removed and added.
+/// Goals are compared as whole obligations, so a tactic which changes
+/// the focused goal closes the old obligation and creates a new one.
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContextDelta {
+    #[pyo3(get)]
+    pub hyps_added: Vec<String>,
+    #[pyo3(get)]
+    pub hyps_removed: Vec<String>,
+    /// Pairs of the hypothesis before and after the tactic
+    #[pyo3(get)]
+    pub hyps_modified: Vec<(String, String)>,
+    #[pyo3(get)]
+    pub focused_goal_changed: bool,
+    /// Obligations which were open before the tactic and are neither
+    /// open nor shelved after it
+    #[pyo3(get)]
+    pub goals_closed: Vec<Obligation>,
+    /// Obligations which are open after the tactic but weren't before
+    #[pyo3(get)]
+    pub goals_created: Vec<Obligation>,
+    /// Obligations which were shelved by the tactic
+    #[pyo3(get)]
+    pub goals_shelved: Vec<Obligation>,
+}
+
+#[pymethods]
+impl ContextDelta {
+    /// Whether the tactic left the context exactly as it found it.
+    pub fn is_empty(&self) -> bool {
+        self.hyps_added.is_empty()
+            && self.hyps_removed.is_empty()
+            && self.hyps_modified.is_empty()
+            && !self.focused_goal_changed
+            && self.goals_closed.is_empty()
+            && self.goals_created.is_empty()
+            && self.goals_shelved.is_empty()
+    }
+}
+
+fn hyp_key(hyp: &str) -> Vec<&str> {
+    match parse_hypothesis(hyp) {
+        Ok(parsed) => parsed.names,
+        Err(_) => vec![hyp],
+    }
+}
+
+/// The obligations in before which aren't in after. Duplicates count
+/// separately, so an obligation that appears twice in before and once
+/// in after is in the result once.
+fn obligations_difference(before: &[Obligation], after: &[Obligation]) -> Vec<Obligation> {
+    let mut remaining: Vec<&Obligation> = after.iter().collect();
+    before
+        .iter()
+        .filter(|obl| match remaining.iter().position(|other| other == obl) {
+            Some(idx) => {
+                remaining.swap_remove(idx);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+pub fn context_delta(before: &ProofContext, after: &ProofContext) -> ContextDelta {
+    let before_hyps: HashMap<Vec<&str>, &String> = before
+        .focused_hyps()
+        .iter()
+        .map(|hyp| (hyp_key(hyp), hyp))
+        .collect();
+    let after_hyps: HashMap<Vec<&str>, &String> = after
+        .focused_hyps()
+        .iter()
+        .map(|hyp| (hyp_key(hyp), hyp))
+        .collect();
+    let mut hyps_modified = Vec::new();
+    let mut hyps_removed = Vec::new();
+    for hyp in before.focused_hyps() {
+        match after_hyps.get(&hyp_key(hyp)) {
+            Some(after_hyp) if *after_hyp != hyp => {
+                hyps_modified.push((hyp.clone(), (*after_hyp).clone()))
+            }
+            Some(_) => (),
+            None => hyps_removed.push(hyp.clone()),
+        }
+    }
+    let hyps_added = after
+        .focused_hyps()
+        .iter()
+        .filter(|hyp| !before_hyps.contains_key(&hyp_key(hyp)))
+        .cloned()
+        .collect();
+
+    let open_before: Vec<Obligation> = before
+        .fg_goals
+        .iter()
+        .chain(before.bg_goals.iter())
+        .cloned()
+        .collect();
+    let open_after: Vec<Obligation> = after
+        .fg_goals
+        .iter()
+        .chain(after.bg_goals.iter())
+        .cloned()
+        .collect();
+    let goals_shelved = obligations_difference(&after.shelved_goals, &before.shelved_goals);
+    let goals_closed = obligations_difference(
+        &obligations_difference(&open_before, &open_after),
+        &goals_shelved,
+    );
+    ContextDelta {
+        hyps_added,
+        hyps_removed,
+        hyps_modified,
+        focused_goal_changed: before.focused_goal() != after.focused_goal(),
+        goals_closed,
+        goals_created: obligations_difference(&open_after, &open_before),
+        goals_shelved,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(goals: Vec<(Vec<&str>, &str)>) -> ProofContext {
+        ProofContext {
+            fg_goals: goals
+                .into_iter()
+                .map(|(hyps, goal)| Obligation {
+                    hypotheses: hyps.into_iter().map(|h| h.to_string()).collect(),
+                    goal: goal.to_string(),
+                })
+                .collect(),
+            ..ProofContext::empty()
+        }
+    }
+
+    #[test]
+    fn test_context_delta() {
+        let before = context(vec![(vec!["n : nat", "H : n = 0"], "n + 0 = n")]);
+        let after = context(vec![
+            (vec!["n : nat", "H : 0 = n", "m : nat"], "n = n"),
+            (vec!["n : nat"], "True"),
+        ]);
+        let delta = context_delta(&before, &after);
+        assert_eq!(delta.hyps_added, vec!["m : nat".to_string()]);
+        assert!(delta.hyps_removed.is_empty());
+        assert_eq!(
+            delta.hyps_modified,
+            vec![("H : n = 0".to_string(), "H : 0 = n".to_string())]
+        );
+        assert!(delta.focused_goal_changed);
+        assert_eq!(delta.goals_closed.len(), 1);
+        assert_eq!(delta.goals_created.len(), 2);
+        assert!(context_delta(&before, &before).is_empty());
+        assert_eq!(
+            context_delta(&before, &ProofContext::empty()).goals_closed,
+            before.fg_goals
+        );
+    }
+}
@@ -20,86 +20,341 @@
 /* *********************************************************************** */
 
 
-extern crate regex;
+use edit_distance::edit_distance;
+use lalrpop_util::ParseError;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
 use rayon::prelude::*;
-use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
 
+use crate::command_kind::CommandKind;
 use crate::scraped_data::*;
 
-use crate::context_filter_ast::ContextFilterAST;
-
-use crate::tokenizer::get_words;
+use crate::context_filter_ast::{ContextFilterAST, PyPredicateErrors, StateQuantity};
+use crate::tactic_analysis::TacticAnalysis;
+use crate::tactic_ast::parse_tactic;
+use crate::tactic_signature::TacticSignatures;
 use lalrpop_util::lalrpop_mod;
 
 lalrpop_mod!(context_filter_parser);
 
+lazy_static! {
+    /// Predicates registered from python, which filters refer to as
+    /// py:name
+    static ref PY_PREDICATES: RwLock<HashMap<String, PyObject>> = RwLock::new(HashMap::new());
+}
+
+/// Registers a python callable, which takes a ScrapedTactic and returns
+/// a bool, for filters to use as py:name. Registering a name again
+/// replaces its predicate in filters parsed after that.
+pub fn register_py_predicate(name: &str, predicate: PyObject) -> PyResult<()> {
+    let mut chars = name.chars();
+    if !(chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_alphanumeric() || c == '_'))
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "Invalid filter predicate name \"{}\", should be a letter followed by \
+             letters, digits and underscores",
+            name
+        )));
+    }
+    PY_PREDICATES
+        .write()
+        .unwrap()
+        .insert(name.to_string(), predicate);
+    Ok(())
+}
+
+pub fn lookup_py_predicate(name: &str) -> Option<PyObject> {
+    PY_PREDICATES.read().unwrap().get(name).cloned()
+}
+
+/// Calls a python predicate on a sample. This can be called from any
+/// thread, including rayon workers, as long as the thread which started
+/// them doesn't hold the GIL while it waits for them. If the predicate
+/// raises, or doesn't return a bool, the sample is rejected and the
+/// error recorded in errors. Once there's an error, every later sample
+/// is rejected without calling the predicate, since the caller is going
+/// to raise anyway.
+fn call_py_predicate(
+    predicate: &PyObject,
+    errors: &PyPredicateErrors,
+    scraped: &ScrapedTactic,
+) -> bool {
+    if errors.has_error() {
+        return false;
+    }
+    Python::with_gil(|py| {
+        // Another worker may have recorded an error while this one
+        // waited for the GIL.
+        if errors.has_error() {
+            return false;
+        }
+        match predicate
+            .call1(py, (scraped.clone(),))
+            .and_then(|result| result.extract::<bool>(py))
+        {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                errors.record(err);
+                false
+            }
+        }
+    })
+}
+
+/// Raises the first error that one of filter's python predicates
+/// raised while it was applied. Call this after applying a filter
+/// which came from python.
+pub fn check_py_predicates(filter: &ContextFilterAST) -> PyResult<()> {
+    match filter.take_py_error() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
 pub fn filter_data_by_key<A: Send>(
     args: &DataloaderArgs,
-    filter_spec: &str,
+    signatures: &TacticSignatures,
+    filter: &ContextFilterAST,
     data: Vec<A>,
     key: fn(&A) -> &ScrapedTactic,
-) -> Vec<A> {
-    let parsed_filter = context_filter_parser::ToplevelFilterParser::new()
-        .parse(filter_spec)
-        .expect(&format!("Invalid context filter: {}", filter_spec));
-    data.into_par_iter()
-        .filter(|datum| apply_filter(args, &parsed_filter, key(datum)))
-        .collect()
+) -> PyResult<Vec<A>> {
+    let filtered = data
+        .into_par_iter()
+        .filter(|datum| apply_filter(args, signatures, filter, key(datum)))
+        .collect();
+    check_py_predicates(filter)?;
+    Ok(filtered)
 }
 
+/// Filters data on rayon workers. If the filter calls python
+/// predicates, the calling thread mustn't hold the GIL.
 pub fn filter_data(
     args: &DataloaderArgs,
-    filter_spec: &str,
+    signatures: &TacticSignatures,
+    filter: &ContextFilterAST,
     data: Vec<ScrapedTactic>,
-) -> Vec<ScrapedTactic> {
-    let parsed_filter = context_filter_parser::ToplevelFilterParser::new()
-        .parse(filter_spec)
-        .expect(&format!("Invalid context filter: {}", filter_spec));
-    data.into_par_iter()
-        .filter(|datum| apply_filter(args, &parsed_filter, datum))
-        .collect()
+) -> PyResult<Vec<ScrapedTactic>> {
+    let filtered = data
+        .into_par_iter()
+        .filter(|datum| apply_filter(args, signatures, filter, datum))
+        .collect();
+    check_py_predicates(filter)?;
+    Ok(filtered)
+}
+
+/// The words a filter can start with, for suggestions when a filter
+/// doesn't parse. This has to match the keywords in
+/// context_filter_parser.lalrpop, which test_filter_keywords checks.
+const FILTER_KEYWORDS: &[&str] = &[
+    "none",
+    "all",
+    "goal-args",
+    "hyp-args",
+    "rel-lemma-args",
+    "numeric-args",
+    "no-semis",
+    "default",
+    "tactic",
+    "maxargs",
+    "minargs",
+    "num-hyps",
+    "goal-len",
+    "num-goals",
+    "goal-changed",
+    "hyps-changed",
+    "closes-goal",
+    "creates-goals",
+    "no-op",
+    "py",
+];
+
+/// Why a context filter string didn't parse.
+#[derive(Debug, Clone)]
+pub struct FilterParseError {
+    pub spec: String,
+    /// The byte range of the filter where the problem is
+    pub span: (usize, usize),
+    pub msg: String,
+    /// The tokens the parser would have accepted there
+    pub expected: Vec<String>,
+    /// The filter keyword closest to the word at the problem, if
+    /// there's one close enough
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid context filter \"{}\": {} at {}..{}",
+            self.spec, self.msg, self.span.0, self.span.1
+        )?;
+        if !self.expected.is_empty() {
+            write!(f, ", expected one of {}", self.expected.join(", "))?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; did you mean \"{}\"?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+impl From<FilterParseError> for PyErr {
+    fn from(err: FilterParseError) -> PyErr {
+        exceptions::PyValueError::new_err(err.to_string())
+    }
+}
+
+/// The filter keyword closest to the word around a position, if it's
+/// within a few edits of it.
+fn suggest_keyword(spec: &str, pos: usize) -> Option<String> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    let start = spec[..pos]
+        .rfind(|c| !is_word_char(c))
+        .map_or(0, |idx| idx + 1);
+    let end = spec[pos..]
+        .find(|c| !is_word_char(c))
+        .map_or(spec.len(), |idx| pos + idx);
+    let word = &spec[start..end];
+    if word.is_empty() || FILTER_KEYWORDS.contains(&word) {
+        return None;
+    }
+    FILTER_KEYWORDS
+        .iter()
+        .map(|keyword| (edit_distance(word, keyword), keyword))
+        .filter(|(distance, _keyword)| *distance <= 3 && *distance < word.len())
+        .min_by_key(|(distance, _keyword)| *distance)
+        .map(|(_distance, keyword)| keyword.to_string())
 }
 
-pub fn parse_filter(filter_spec: &str) -> ContextFilterAST {
+pub fn parse_filter(filter_spec: &str) -> Result<ContextFilterAST, FilterParseError> {
     context_filter_parser::ToplevelFilterParser::new()
         .parse(filter_spec)
-        .expect(&format!("Invalid context filter: {}", filter_spec))
+        .map_err(|err| {
+            let char_end = |pos: usize| {
+                filter_spec[pos..]
+                    .chars()
+                    .next()
+                    .map_or(pos, |c| pos + c.len_utf8())
+            };
+            let (span, msg, expected) = match err {
+                ParseError::InvalidToken { location } => (
+                    (location, char_end(location)),
+                    format!("unexpected \"{}\"", &filter_spec[location..char_end(location)]),
+                    vec![],
+                ),
+                ParseError::UnrecognizedEOF { location, expected } => {
+                    ((location, location), "unexpected end".to_string(), expected)
+                }
+                ParseError::UnrecognizedToken {
+                    token: (start, _, end),
+                    expected,
+                } => (
+                    (start, end),
+                    format!("unexpected \"{}\"", &filter_spec[start..end]),
+                    expected,
+                ),
+                ParseError::ExtraToken {
+                    token: (start, _, end),
+                } => (
+                    (start, end),
+                    format!("extra \"{}\"", &filter_spec[start..end]),
+                    vec![],
+                ),
+                ParseError::User {
+                    error: (start, end, msg),
+                } => ((start, end), msg.to_string(), vec![]),
+            };
+            FilterParseError {
+                spec: filter_spec.to_string(),
+                span,
+                msg,
+                expected,
+                suggestion: suggest_keyword(filter_spec, span.0),
+            }
+        })
+}
+
+/// Parses a filter to apply to tactics on their own, which rules out
+/// filters that look at the context after the tactic.
+pub fn parse_tactic_filter(filter_spec: &str) -> Result<ContextFilterAST, FilterParseError> {
+    let filter = parse_filter(filter_spec)?;
+    if filter.uses_transitions() {
+        return Err(FilterParseError {
+            spec: filter_spec.to_string(),
+            span: (0, filter_spec.len()),
+            msg: "transition filters can't be applied to tactics alone".to_string(),
+            expected: vec![],
+            suggestion: None,
+        });
+    }
+    Ok(filter)
 }
 
 pub fn apply_filter(
     args: &DataloaderArgs,
+    signatures: &TacticSignatures,
     parsed_filter: &ContextFilterAST,
     scraped: &ScrapedTactic,
 ) -> bool {
+    filter_accepts(args, signatures, parsed_filter, &TacticAnalysis::new(scraped, None))
+}
+
+pub fn apply_transition_filter(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    parsed_filter: &ContextFilterAST,
+    transition: &ScrapedTransition,
+) -> bool {
+    let scraped = transition.scraped_before();
+    filter_accepts(
+        args,
+        signatures,
+        parsed_filter,
+        &TacticAnalysis::new(&scraped, Some(&transition.after)),
+    )
+}
+
+/// Applies a filter to an analyzed sample. Transition filters panic
+/// on samples without an after context, so filters for plain tactics
+/// should come from parse_tactic_filter.
+fn filter_accepts(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    parsed_filter: &ContextFilterAST,
+    analysis: &TacticAnalysis,
+) -> bool {
+    let scraped = analysis.scraped;
     match parsed_filter {
         ContextFilterAST::And(subfilters) => subfilters
             .iter()
-            .all(|subfilter| apply_filter(args, subfilter, scraped)),
+            .all(|subfilter| filter_accepts(args, signatures, subfilter, analysis)),
         ContextFilterAST::Or(subfilters) => subfilters
             .iter()
-            .any(|subfilter| apply_filter(args, subfilter, scraped)),
+            .any(|subfilter| filter_accepts(args, signatures, subfilter, analysis)),
+        ContextFilterAST::Not(subfilter) => !filter_accepts(args, signatures, subfilter, analysis),
         ContextFilterAST::None => false,
         ContextFilterAST::All => true,
         ContextFilterAST::GoalArgs => {
-            let goal_symbols: Vec<&str> = get_words(&scraped.context.focused_goal())
-                .into_iter()
-                .take(args.max_length)
-                .collect();
-            let (tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
+            let goal_words = analysis.goal_words();
+            let goal_symbols = &goal_words[..goal_words.len().min(args.max_length)];
+            let (tactic_stem, arg_tokens) = match analysis.stem_and_args() {
                 None => return false,
                 Some(x) => x,
             };
-            let mut trimmed_args = tactic_argstr.trim();
-            if trimmed_args.chars().last() == Some('.') {
-                trimmed_args = &trimmed_args[..trimmed_args.len() - 1]
-            }
-            let arg_tokens: Vec<&str> = trimmed_args.split_whitespace().collect();
             // While the arguments to an intro(s) might *look* like
             // goal arguments, they are actually fresh variables
-            if (tactic_stem == "intros" || tactic_stem == "intro") && arg_tokens.len() > 0 {
+            if signatures.binds_fresh_names(tactic_stem) && arg_tokens.len() > 0 {
                 return false;
             }
-            arg_tokens.into_iter().all(|arg_token| {
+            arg_tokens.iter().all(|arg_token| {
                 goal_symbols
                     .iter()
                     .find(|symbol| symbol_matches(*symbol, arg_token))
@@ -107,102 +362,505 @@ pub fn apply_filter(
             })
         }
         ContextFilterAST::HypArgs => {
-            let hyp_names: Vec<String> =
-                indexed_premises(scraped.context.focused_hyps().iter().map(|s| s.as_ref()))
-                    .into_iter()
-                    .map(|(_idx, hyp_name)| hyp_name)
-                    .collect();
-            let (tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
+            let hyp_names = match analysis.hyp_names() {
+                Some(names) => names,
+                None => return false,
+            };
+            let (tactic_stem, arg_tokens) = match analysis.stem_and_args() {
                 None => return false,
                 Some(x) => x,
             };
-            let mut trimmed_args = tactic_argstr.trim();
-            if trimmed_args.chars().last() == Some('.') {
-                trimmed_args = &trimmed_args[..trimmed_args.len() - 1]
-            }
-            let arg_tokens: Vec<&str> = trimmed_args.split_whitespace().collect();
             // While the arguments to an intro(s) might *look* like
             // hyp arguments, they are actually fresh variables
-            if (tactic_stem == "intros" || tactic_stem == "intro") && arg_tokens.len() > 0 {
+            if signatures.binds_fresh_names(tactic_stem) && arg_tokens.len() > 0 {
                 return false;
             }
-            let result = (tactic_takes_hyp_args(&tactic_stem)
+            let result = (signatures.takes_hyp_args(tactic_stem)
                 && arg_tokens
                     .iter()
-                    .cloned() //.into_iter()
-                    .all(|arg_token| hyp_names.contains(&arg_token.to_string())))
+                    .all(|arg_token| hyp_names.contains(arg_token)))
                 || arg_tokens.len() == 0;
             result
         }
         ContextFilterAST::RelevantLemmaArgs => {
-            let lemma_names: Vec<_> =
-                indexed_premises(scraped.relevant_lemmas.iter().map(|s| s.as_ref()))
-                    .into_iter()
-                    .map(|(_idx, hyp_name)| hyp_name)
-                    .collect();
-            let (tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
+            let lemma_names = match analysis.lemma_names() {
+                Some(names) => names,
+                None => return false,
+            };
+            let (tactic_stem, arg_tokens) = match analysis.stem_and_args() {
                 None => return false,
                 Some(x) => x,
             };
-            let mut trimmed_args = tactic_argstr.trim();
-            if trimmed_args.chars().last() == Some('.') {
-                trimmed_args = &trimmed_args[..trimmed_args.len() - 1]
-            }
-            let arg_tokens: Vec<&str> = trimmed_args.split_whitespace().collect();
-            let result = tactic_takes_hyp_args(&tactic_stem)
+            let result = signatures.takes_lemma_args(tactic_stem)
                 && arg_tokens
                     .iter()
-                    .all(|arg_token| lemma_names.contains(&arg_token.to_string()))
+                    .all(|arg_token| lemma_names.contains(arg_token))
                 || arg_tokens.len() == 0;
             // assert!(!(scraped.tactic.trim() == "induction e." && result));
             // assert!(result, "{}", &scraped.tactic);
             result
         }
-        ContextFilterAST::NumericArgs => {
-            let (_tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
-                None => return false,
-                Some(x) => x,
+        ContextFilterAST::NumericArgs => match analysis.stem_and_args() {
+            None => false,
+            Some((_tactic_stem, arg_tokens)) => arg_tokens
+                .iter()
+                .all(|arg_token| arg_token.chars().all(char::is_numeric)),
+        },
+        ContextFilterAST::NoSemis => match parse_tactic(&scraped.tactic) {
+            Ok(tactic) => !tactic.has_sequencing(),
+            Err(_) => !scraped.tactic.contains(";"),
+        },
+        ContextFilterAST::Tactic(s) => match analysis.stem_and_args() {
+            None => false,
+            Some((tactic_stem, _arg_tokens)) => tactic_stem == s,
+        },
+        ContextFilterAST::TacticRegex(re) => match analysis.stem_and_args() {
+            None => false,
+            Some((tactic_stem, _arg_tokens)) => re.is_match(tactic_stem),
+        },
+        ContextFilterAST::MaxArgs(num) => match analysis.arg_count() {
+            None => false,
+            Some(count) => count as i64 <= *num,
+        },
+        ContextFilterAST::MinArgs(num) => match analysis.arg_count() {
+            None => false,
+            Some(count) => count as i64 >= *num,
+        },
+        ContextFilterAST::Compare(quantity, comparison, num) => {
+            let value = match quantity {
+                StateQuantity::NumHyps => scraped.context.focused_hyps().len(),
+                StateQuantity::GoalLen => analysis.goal_words().len(),
+                StateQuantity::NumGoals => scraped.context.fg_goals.len(),
             };
-            let trimmed_args = tactic_argstr.trim();
-            trimmed_args[..trimmed_args.len() - 1]
-                .split_whitespace()
-                .all(|arg_token| arg_token.chars().all(char::is_numeric))
-        }
-        ContextFilterAST::NoSemis => !scraped.tactic.contains(";"),
-        ContextFilterAST::Tactic(s) => {
-            let (tactic_stem, _tactic_argstr) = match split_tactic(&scraped.tactic) {
-                None => return false,
-                Some(x) => x,
-            };
-            tactic_stem == *s
-        }
-        ContextFilterAST::MaxArgs(num) => {
-            let (_tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
-                None => return false,
-                Some(x) => x,
-            };
-            let trimmed_args = tactic_argstr.trim();
-            trimmed_args[..trimmed_args.len() - 1]
-                .split_whitespace()
-                .collect::<Vec<_>>()
-                .len() as i64
-                <= *num
+            comparison.holds(value, *num)
         }
         ContextFilterAST::Default => {
-            let tactic = kill_comments(&scraped.tactic);
-            lazy_static! {
-                static ref BACKGROUND_TAC: Regex = Regex::new(r"^\d+:.*").unwrap();
-                static ref BULLET: Regex = Regex::new(r"^\s*[\{\}\+\-\*].*").unwrap();
-            }
-            let result = !tactic.contains(";")
-                && !tactic.contains("Proof")
+            let tactic = analysis.code();
+            let result = *analysis.command_kind() == CommandKind::Tactic
+                && !tactic.contains(";")
                 && !tactic.contains("Opaque")
-                && !tactic.contains("Qed")
-                && !tactic.contains("Defined")
-                && !tactic.contains("Unshelve")
-                && !BACKGROUND_TAC.is_match(&tactic)
-                && !BULLET.is_match(&tactic);
+                && !tactic.contains("Unshelve");
             result
         }
+        ContextFilterAST::PyPredicate(_name, predicate, errors) => {
+            call_py_predicate(predicate, errors, scraped)
+        }
+        ContextFilterAST::GoalChanged => analysis.delta().focused_goal_changed,
+        ContextFilterAST::HypsChanged => {
+            let delta = analysis.delta();
+            !(delta.hyps_added.is_empty()
+                && delta.hyps_removed.is_empty()
+                && delta.hyps_modified.is_empty())
+        }
+        ContextFilterAST::ClosesGoal => {
+            let delta = analysis.delta();
+            !delta.goals_closed.is_empty() && delta.goals_created.is_empty()
+        }
+        ContextFilterAST::CreatesGoals(num) => analysis.delta().goals_created.len() == *num,
+        ContextFilterAST::NoOp => analysis.delta().is_empty(),
+    }
+}
+
+/// How each part of a filter did over a dataset, for finding out which
+/// part of a filter is throwing data away. Every node is run on every
+/// sample, even where the filter as a whole wouldn't need to run it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterExplanation {
+    pub filter: String,
+    pub accepted: usize,
+    pub rejected: usize,
+    /// The first few tactics the filter accepted
+    pub accepted_examples: Vec<String>,
+    /// The first few tactics the filter rejected
+    pub rejected_examples: Vec<String>,
+    pub children: Vec<FilterExplanation>,
+}
+
+impl FilterExplanation {
+    fn new(filter: &ContextFilterAST) -> Self {
+        FilterExplanation {
+            filter: filter.to_string(),
+            accepted: 0,
+            rejected: 0,
+            accepted_examples: vec![],
+            rejected_examples: vec![],
+            children: filter.children().into_iter().map(FilterExplanation::new).collect(),
+        }
+    }
+    /// Counts one sample, given the outcomes of each node in pre-order.
+    fn record(
+        &mut self,
+        outcomes: &mut impl Iterator<Item = bool>,
+        tactic: &str,
+        num_examples: usize,
+    ) {
+        let (count, examples) = if outcomes.next().expect("Too few filter outcomes") {
+            (&mut self.accepted, &mut self.accepted_examples)
+        } else {
+            (&mut self.rejected, &mut self.rejected_examples)
+        };
+        *count += 1;
+        if examples.len() < num_examples {
+            examples.push(tactic.to_string());
+        }
+        for child in self.children.iter_mut() {
+            child.record(outcomes, tactic, num_examples);
+        }
+    }
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        let total = self.accepted + self.rejected;
+        writeln!(
+            f,
+            "{}{}: accepted {} of {} ({:.1}%)",
+            indent,
+            self.filter,
+            self.accepted,
+            total,
+            if total == 0 { 0.0 } else { 100.0 * self.accepted as f64 / total as f64 }
+        )?;
+        for (label, examples) in &[
+            ("accepted", &self.accepted_examples),
+            ("rejected", &self.rejected_examples),
+        ] {
+            if !examples.is_empty() {
+                writeln!(f, "{}  {}: {:?}", indent, label, examples)?;
+            }
+        }
+        for child in self.children.iter() {
+            child.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints as a tree, one node per line with its children indented
+/// under it.
+impl fmt::Display for FilterExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// In python, explanations are dicts with the same fields.
+impl IntoPy<PyObject> for FilterExplanation {
+    fn into_py(self, py: Python) -> PyObject {
+        let dict = PyDict::new(py);
+        dict.set_item("filter", self.filter).unwrap();
+        dict.set_item("accepted", self.accepted).unwrap();
+        dict.set_item("rejected", self.rejected).unwrap();
+        dict.set_item("accepted_examples", self.accepted_examples).unwrap();
+        dict.set_item("rejected_examples", self.rejected_examples).unwrap();
+        let children: Vec<PyObject> =
+            self.children.into_iter().map(|child| child.into_py(py)).collect();
+        dict.set_item("children", children).unwrap();
+        dict.into_py(py)
+    }
+}
+
+impl<'source> FromPyObject<'source> for FilterExplanation {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        let dict: &PyDict = obj.downcast()?;
+        let field = |name: &str| {
+            dict.get_item(name)
+                .ok_or_else(|| exceptions::PyKeyError::new_err(name.to_string()))
+        };
+        Ok(FilterExplanation {
+            filter: field("filter")?.extract()?,
+            accepted: field("accepted")?.extract()?,
+            rejected: field("rejected")?.extract()?,
+            accepted_examples: field("accepted_examples")?.extract()?,
+            rejected_examples: field("rejected_examples")?.extract()?,
+            children: field("children")?.extract()?,
+        })
+    }
+}
+
+/// Runs filter, and pushes its outcome and those of all its
+/// subfilters in pre-order.
+fn filter_outcomes(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    filter: &ContextFilterAST,
+    analysis: &TacticAnalysis,
+    outcomes: &mut Vec<bool>,
+) -> bool {
+    let idx = outcomes.len();
+    outcomes.push(false);
+    let child_outcomes: Vec<bool> = filter
+        .children()
+        .into_iter()
+        .map(|child| filter_outcomes(args, signatures, child, analysis, outcomes))
+        .collect();
+    let outcome = match filter {
+        ContextFilterAST::And(_) => child_outcomes.into_iter().all(|outcome| outcome),
+        ContextFilterAST::Or(_) => child_outcomes.into_iter().any(|outcome| outcome),
+        ContextFilterAST::Not(_) => !child_outcomes[0],
+        _ => filter_accepts(args, signatures, filter, analysis),
+    };
+    outcomes[idx] = outcome;
+    outcome
+}
+
+/// Explains a filter over tactics, each with the context after it if
+/// the filter uses transitions.
+pub fn explain_filter(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    filter: &ContextFilterAST,
+    data: &[(ScrapedTactic, Option<ProofContext>)],
+    num_examples: usize,
+) -> FilterExplanation {
+    let all_outcomes: Vec<Vec<bool>> = data
+        .par_iter()
+        .map(|(scraped, after)| {
+            let mut outcomes = Vec::new();
+            let analysis = TacticAnalysis::new(scraped, after.as_ref());
+            filter_outcomes(args, signatures, filter, &analysis, &mut outcomes);
+            outcomes
+        })
+        .collect();
+    let mut explanation = FilterExplanation::new(filter);
+    for ((scraped, _after), outcomes) in data.iter().zip(all_outcomes) {
+        explanation.record(&mut outcomes.into_iter(), &scraped.tactic, num_examples);
+    }
+    explanation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_grammar() {
+        let scraped = ScrapedTactic {
+            relevant_lemmas: vec![],
+            prev_tactics: vec![],
+            context: ProofContext {
+                fg_goals: vec![Obligation {
+                    hypotheses: vec!["H : x = y".to_string(), "x, y : nat".to_string()],
+                    goal: "y = x".to_string(),
+                }],
+                bg_goals: vec![],
+                shelved_goals: vec![],
+                given_up_goals: vec![],
+            },
+            tactic: "eapply eq_sym with (x := y).".to_string(),
+            provenance: None,
+        };
+        let args: DataloaderArgs = Default::default();
+        let signatures = TacticSignatures::default();
+        let accepts =
+            |spec: &str| apply_filter(&args, &signatures, &parse_filter(spec).unwrap(), &scraped);
+        assert!(accepts("default"));
+        assert!(!accepts("!default"));
+        assert!(accepts("!(goal-args % hyp-args)"));
+        assert!(accepts("minargs:3 % maxargs:10"));
+        assert!(!accepts("minargs:20"));
+        assert!(accepts("tactic~/^e?apply$/"));
+        assert!(!accepts("tactic~/^apply/"));
+        assert!(accepts("num-hyps<3 % num-hyps>=2 % goal-len=3 % num-goals=1"));
+        assert!(accepts("num-goals>1 + !tactic:apply"));
+        assert!(!accepts("num-goals>1 + tactic:apply"));
+    }
+
+    #[test]
+    fn test_goal_args() {
+        let args = DataloaderArgs {
+            max_length: 30,
+            ..Default::default()
+        };
+        let signatures = TacticSignatures::default();
+        let filter = parse_filter("goal-args").unwrap();
+        let accepts = |tactic: &str| {
+            let scraped = ScrapedTactic {
+                relevant_lemmas: vec![],
+                prev_tactics: vec![],
+                context: ProofContext {
+                    fg_goals: vec![Obligation {
+                        hypotheses: vec![],
+                        goal: "forall n, double n = n + n".to_string(),
+                    }],
+                    ..ProofContext::empty()
+                },
+                tactic: tactic.to_string(),
+                provenance: None,
+            };
+            apply_filter(&args, &signatures, &filter, &scraped)
+        };
+        assert!(accepts("unfold double."));
+        // Only tactics which bind fresh names are left out, so this
+        // accepts rewrites by goal tokens too.
+        assert!(accepts("rewrite double."));
+        assert!(accepts("auto."));
+        assert!(!accepts("intros n."));
+        assert!(!accepts("unfold triple."));
+    }
+
+    #[test]
+    fn test_filter_parse_errors() {
+        let err = parse_filter("goal-arg % hyp-args").err().unwrap();
+        assert_eq!(err.span, (0, 4));
+        assert_eq!(err.suggestion, Some("goal-args".to_string()));
+        let err = parse_filter("default % maxargs:").err().unwrap();
+        assert_eq!(err.span, (18, 18));
+        assert_eq!(err.expected, vec!["r#\"[0-9]+\"#".to_string()]);
+        let err = parse_filter("tactic~/(/").err().unwrap();
+        assert_eq!((err.span, err.msg.as_str()), ((7, 10), "invalid regex"));
+        let err = parse_filter("default % py:unregistered").err().unwrap();
+        assert_eq!((err.span, err.msg.as_str()), ((13, 25), "unregistered python predicate"));
+        assert!(parse_filter("(default % no-semis) + !hyp-args").is_ok());
+    }
+
+    #[test]
+    fn test_py_predicates() {
+        let data: Vec<ScrapedTactic> = (0..100)
+            .map(|idx| ScrapedTactic {
+                relevant_lemmas: vec![],
+                prev_tactics: vec![],
+                context: ProofContext::empty(),
+                tactic: if idx % 4 == 0 { "auto." } else { "simpl." }.to_string(),
+                provenance: None,
+            })
+            .collect();
+        let args: DataloaderArgs = Default::default();
+        let signatures = TacticSignatures::default();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            py.run(
+                "calls = []\n\
+                 def is_auto(t):\n    return t.tactic == 'auto.'\n\
+                 def broken(t):\n    calls.append(t)\n    raise ZeroDivisionError()\n",
+                Some(globals),
+                None,
+            )
+            .unwrap();
+            for name in &["is_auto", "broken"] {
+                let predicate = globals.get_item(name).unwrap().to_object(py);
+                register_py_predicate(&format!("test_{}", name), predicate).unwrap();
+            }
+
+            let filter = parse_filter("py:test_is_auto").unwrap();
+            let filtered =
+                py.allow_threads(|| filter_data(&args, &signatures, &filter, data.clone()));
+            assert_eq!(filtered.unwrap().len(), 25);
+
+            let filter = parse_filter("py:test_broken").unwrap();
+            let err = py
+                .allow_threads(|| filter_data(&args, &signatures, &filter, data.clone()))
+                .err()
+                .unwrap();
+            assert!(err.is_instance::<exceptions::PyZeroDivisionError>(py));
+            let calls = py.eval("len(calls)", Some(globals), None).unwrap();
+            assert_eq!(calls.extract::<usize>().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn test_filter_keywords() {
+        // Every word-like terminal of the grammar is a keyword.
+        let grammar = include_str!("context_filter_parser.lalrpop");
+        let terminal = regex::Regex::new(r#""([a-z][a-z-]*)""#).unwrap();
+        let mut grammar_keywords: Vec<&str> = terminal
+            .captures_iter(grammar)
+            .map(|captures| captures.get(1).unwrap().as_str())
+            .collect();
+        grammar_keywords.sort();
+        grammar_keywords.dedup();
+        let mut keywords = FILTER_KEYWORDS.to_vec();
+        keywords.sort();
+        assert_eq!(keywords, grammar_keywords);
+        // Each keyword starts a filter, so a filter that's only the
+        // keyword either parses or fails after it.
+        for keyword in FILTER_KEYWORDS {
+            if let Err(err) = parse_filter(keyword) {
+                assert_eq!(err.span.0, keyword.len(), "{}", keyword);
+            }
+        }
+    }
+
+    #[test]
+    fn test_explain_filter() {
+        let scraped = |tactic: &str, num_hyps: usize| ScrapedTactic {
+            relevant_lemmas: vec![],
+            prev_tactics: vec![],
+            context: ProofContext {
+                fg_goals: vec![Obligation {
+                    hypotheses: (0..num_hyps).map(|idx| format!("H{} : True", idx)).collect(),
+                    goal: "True".to_string(),
+                }],
+                bg_goals: vec![],
+                shelved_goals: vec![],
+                given_up_goals: vec![],
+            },
+            tactic: tactic.to_string(),
+            provenance: None,
+        };
+        let data: Vec<_> = vec![
+            scraped("auto.", 0),
+            scraped("apply H0.", 1),
+            scraped("apply H1.", 2),
+            scraped("- auto.", 2),
+        ]
+        .into_iter()
+        .map(|scraped| (scraped, None))
+        .collect();
+        let filter = parse_filter("(tactic:apply + !default) % num-hyps<2").unwrap();
+        let args: DataloaderArgs = Default::default();
+        let explanation =
+            explain_filter(&args, &TacticSignatures::default(), &filter, &data, 1);
+        assert_eq!(explanation.filter, "(tactic:apply + !default) % num-hyps<2");
+        assert_eq!((explanation.accepted, explanation.rejected), (1, 3));
+        assert_eq!(explanation.accepted_examples, vec!["apply H0."]);
+        let or = &explanation.children[0];
+        assert_eq!((or.accepted, or.rejected), (3, 1));
+        assert_eq!(or.rejected_examples, vec!["auto."]);
+        let not = &or.children[1];
+        assert_eq!((not.filter.as_str(), not.accepted), ("!default", 1));
+        assert_eq!(not.children[0].filter, "default");
+        let hyps = &explanation.children[1];
+        assert_eq!((hyps.accepted, hyps.rejected), (2, 2));
+        assert_eq!(
+            explanation.to_string().lines().nth(2).unwrap(),
+            "  rejected: [\"auto.\"]"
+        );
+    }
+
+    #[test]
+    fn test_transition_filters() {
+        let context = |goals: Vec<&str>| ProofContext {
+            fg_goals: goals
+                .into_iter()
+                .map(|goal| Obligation {
+                    hypotheses: vec!["H : A".to_string()],
+                    goal: goal.to_string(),
+                })
+                .collect(),
+            ..ProofContext::empty()
+        };
+        let transition = |tactic: &str, before, after| ScrapedTransition {
+            relevant_lemmas: vec![],
+            prev_tactics: vec![],
+            before,
+            after,
+            tactic: tactic.to_string(),
+            provenance: None,
+            linearized: None,
+            partial: false,
+        };
+        let split = transition("split.", context(vec!["A /\\ A"]), context(vec!["A", "A"]));
+        let exact = transition("exact H.", context(vec!["A", "B"]), context(vec!["B"]));
+        let idtac = transition("idtac.", context(vec!["A"]), context(vec!["A"]));
+        let args: DataloaderArgs = Default::default();
+        let signatures = TacticSignatures::default();
+        let accepts = |spec: &str, transition: &ScrapedTransition| {
+            apply_transition_filter(&args, &signatures, &parse_filter(spec).unwrap(), transition)
+        };
+        assert!(accepts("goal-changed % creates-goals:2 % !closes-goal", &split));
+        assert!(!accepts("hyps-changed + no-op", &split));
+        assert!(accepts("closes-goal % goal-changed % creates-goals:0", &exact));
+        assert!(accepts("no-op % !goal-changed % default", &idtac));
+        assert!(parse_tactic_filter("default % !no-op").is_err());
+        assert!(parse_tactic_filter("default % !hyp-args").is_ok());
     }
 }
@@ -19,9 +19,88 @@
 //
 /* *********************************************************************** */
 
+use pyo3::{PyErr, PyObject};
+use regex::Regex;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A number that can be compared against in a filter, like the
+/// "num-hyps" in "num-hyps<10".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateQuantity {
+    /// The number of hypotheses of the focused goal
+    NumHyps,
+    /// The number of words in the focused goal
+    GoalLen,
+    /// The number of goals in focus
+    NumGoals,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl StateQuantity {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            StateQuantity::NumHyps => "num-hyps",
+            StateQuantity::GoalLen => "goal-len",
+            StateQuantity::NumGoals => "num-goals",
+        }
+    }
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Eq => "=",
+            Comparison::Ge => ">=",
+            Comparison::Gt => ">",
+        }
+    }
+    pub fn holds(&self, left: usize, right: usize) -> bool {
+        match self {
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Eq => left == right,
+            Comparison::Ge => left >= right,
+            Comparison::Gt => left > right,
+        }
+    }
+}
+
+/// The first error a python predicate raised. Filters are applied on
+/// rayon workers, which can't raise, so a predicate that fails rejects
+/// the sample and leaves its error here for the filter's caller.
+#[derive(Default)]
+pub struct PyPredicateErrors(Mutex<Option<PyErr>>);
+
+impl PyPredicateErrors {
+    pub fn record(&self, err: PyErr) {
+        let mut first = self.0.lock().unwrap();
+        if first.is_none() {
+            *first = Some(err);
+        }
+    }
+    pub fn has_error(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+    pub fn take(&self) -> Option<PyErr> {
+        self.0.lock().unwrap().take()
+    }
+}
+
 pub enum ContextFilterAST {
     And(Vec<ContextFilterAST>),
     Or(Vec<ContextFilterAST>),
+    Not(Box<ContextFilterAST>),
 
     None,
     All,
@@ -33,5 +112,104 @@ pub enum ContextFilterAST {
     Default,
 
     Tactic(String),
+    /// Tactics whose stem matches a regex anywhere
+    TacticRegex(Regex),
     MaxArgs(i64),
+    MinArgs(i64),
+    Compare(StateQuantity, Comparison, usize),
+    /// A predicate registered from python, its name, and the errors
+    /// it has raised
+    PyPredicate(String, PyObject, PyPredicateErrors),
+
+    // These look at the context after the tactic, so they can only be
+    // applied to transitions.
+    GoalChanged,
+    HypsChanged,
+    /// Tactics which close goals without creating any
+    ClosesGoal,
+    /// Tactics which leave exactly this many new obligations
+    CreatesGoals(usize),
+    NoOp,
+}
+
+impl ContextFilterAST {
+    /// The filters this one is built from, if it's compound.
+    pub fn children(&self) -> Vec<&ContextFilterAST> {
+        match self {
+            ContextFilterAST::And(subfilters) | ContextFilterAST::Or(subfilters) => {
+                subfilters.iter().collect()
+            }
+            ContextFilterAST::Not(subfilter) => vec![subfilter],
+            _ => vec![],
+        }
+    }
+    /// Whether this filter needs to see the context after the tactic.
+    pub fn uses_transitions(&self) -> bool {
+        match self {
+            ContextFilterAST::GoalChanged
+            | ContextFilterAST::HypsChanged
+            | ContextFilterAST::ClosesGoal
+            | ContextFilterAST::CreatesGoals(_)
+            | ContextFilterAST::NoOp => true,
+            _ => self.children().into_iter().any(ContextFilterAST::uses_transitions),
+        }
+    }
+    /// Takes the first error that one of this filter's python
+    /// predicates raised while it was applied.
+    pub fn take_py_error(&self) -> Option<PyErr> {
+        match self {
+            ContextFilterAST::PyPredicate(_name, _predicate, errors) => errors.take(),
+            _ => self.children().into_iter().find_map(ContextFilterAST::take_py_error),
+        }
+    }
+}
+
+/// Filters display in the syntax they're parsed from, so printing a
+/// parsed filter gives an equivalent filter spec.
+impl fmt::Display for ContextFilterAST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let write_operand = |f: &mut fmt::Formatter, subfilter: &ContextFilterAST| match subfilter {
+            ContextFilterAST::And(_) | ContextFilterAST::Or(_) => write!(f, "({})", subfilter),
+            _ => write!(f, "{}", subfilter),
+        };
+        match self {
+            ContextFilterAST::And(subfilters) | ContextFilterAST::Or(subfilters) => {
+                let op = if let ContextFilterAST::And(_) = self { " % " } else { " + " };
+                for (idx, subfilter) in subfilters.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, "{}", op)?;
+                    }
+                    write_operand(f, subfilter)?;
+                }
+                Ok(())
+            }
+            ContextFilterAST::Not(subfilter) => {
+                write!(f, "!")?;
+                write_operand(f, subfilter)
+            }
+            ContextFilterAST::None => write!(f, "none"),
+            ContextFilterAST::All => write!(f, "all"),
+            ContextFilterAST::GoalArgs => write!(f, "goal-args"),
+            ContextFilterAST::HypArgs => write!(f, "hyp-args"),
+            ContextFilterAST::RelevantLemmaArgs => write!(f, "rel-lemma-args"),
+            ContextFilterAST::NumericArgs => write!(f, "numeric-args"),
+            ContextFilterAST::NoSemis => write!(f, "no-semis"),
+            ContextFilterAST::Default => write!(f, "default"),
+            ContextFilterAST::Tactic(stem) => write!(f, "tactic:{}", stem),
+            ContextFilterAST::TacticRegex(re) => {
+                write!(f, "tactic~/{}/", re.as_str().replace("/", "\\/"))
+            }
+            ContextFilterAST::MaxArgs(num) => write!(f, "maxargs:{}", num),
+            ContextFilterAST::MinArgs(num) => write!(f, "minargs:{}", num),
+            ContextFilterAST::Compare(quantity, comparison, num) => {
+                write!(f, "{}{}{}", quantity.keyword(), comparison.symbol(), num)
+            }
+            ContextFilterAST::PyPredicate(name, _predicate, _errors) => write!(f, "py:{}", name),
+            ContextFilterAST::GoalChanged => write!(f, "goal-changed"),
+            ContextFilterAST::HypsChanged => write!(f, "hyps-changed"),
+            ContextFilterAST::ClosesGoal => write!(f, "closes-goal"),
+            ContextFilterAST::CreatesGoals(num) => write!(f, "creates-goals:{}", num),
+            ContextFilterAST::NoOp => write!(f, "no-op"),
+        }
+    }
 }
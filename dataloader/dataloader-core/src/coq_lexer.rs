@@ -0,0 +1,232 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexemeKind {
+    Ident,
+    /// A dotted path like Nat.add_comm
+    QualifiedName,
+    Keyword,
+    /// Notation symbols, and any other character that isn't part of
+    /// an identifier, like ∀ or →
+    Symbol,
+    Numeral,
+    StringLit,
+    Comment,
+}
+
+/// A token of Coq source, with its byte range in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lexeme<'a> {
+    pub kind: LexemeKind,
+    pub text: &'a str,
+    pub start: usize,
+    pub end: usize,
+}
+
+const KEYWORDS: &[&str] = &[
+    "forall", "fun", "exists", "match", "with", "end", "let", "in", "as", "return", "if", "then",
+    "else", "fix", "cofix", "struct", "Type", "Prop", "Set", "SProp",
+];
+
+/// Multi-character symbols, longest first where one is a prefix of
+/// another. Any other character outside an identifier is a symbol by
+/// itself.
+const SYMBOLS: &[&str] = &[
+    "<->", "<=", ">=", "<>", ":=", ":>", "::", "=>", "->", "<-", "/\\", "\\/", "++", "**", "&&",
+    "||", "|-", "@@", "@{", "{|", "|}", "..",
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+fn char_at(source: &str, idx: usize) -> Option<char> {
+    source[idx..].chars().next()
+}
+
+fn skip_ident(source: &str, idx: usize) -> usize {
+    source[idx..]
+        .find(|c: char| !is_ident_char(c))
+        .map_or(source.len(), |len| idx + len)
+}
+
+/// Skips a string literal starting at idx, where "" stands for a
+/// quote. Unterminated strings run to the end of the source.
+fn skip_string(source: &str, idx: usize) -> usize {
+    let mut pos = idx + 1;
+    loop {
+        match source[pos..].find('"') {
+            None => return source.len(),
+            Some(len) => {
+                pos += len + 1;
+                if char_at(source, pos) != Some('"') {
+                    return pos;
+                }
+                pos += 1;
+            }
+        }
+    }
+}
+
+/// Skips a (possibly nested) comment starting at idx. Like Coq, this
+/// skips string literals inside comments, so "*)" in one doesn't end
+/// the comment. Unterminated comments run to the end of the source.
+fn skip_comment(source: &str, idx: usize) -> usize {
+    let mut depth = 0;
+    let mut pos = idx;
+    while let Some(c) = char_at(source, pos) {
+        if source[pos..].starts_with("(*") {
+            depth += 1;
+            pos += 2;
+        } else if source[pos..].starts_with("*)") {
+            depth -= 1;
+            pos += 2;
+            if depth == 0 {
+                return pos;
+            }
+        } else if c == '"' {
+            pos = skip_string(source, pos);
+        } else {
+            pos += c.len_utf8();
+        }
+    }
+    source.len()
+}
+
+/// Splits Coq source into lexemes. This never fails: anything that
+/// isn't recognized becomes a one character symbol.
+pub fn lex(source: &str) -> Vec<Lexeme<'_>> {
+    let mut lexemes = Vec::new();
+    let mut idx = 0;
+    while let Some(c) = char_at(source, idx) {
+        if c.is_whitespace() {
+            idx += c.len_utf8();
+            continue;
+        }
+        let start = idx;
+        let kind = if source[idx..].starts_with("(*") {
+            idx = skip_comment(source, idx);
+            LexemeKind::Comment
+        } else if c == '"' {
+            idx = skip_string(source, idx);
+            LexemeKind::StringLit
+        } else if c.is_ascii_digit() {
+            idx = source[idx..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '_'))
+                .map_or(source.len(), |len| idx + len);
+            LexemeKind::Numeral
+        } else if is_ident_start(c)
+            || (c == '?' && char_at(source, idx + 1).map_or(false, is_ident_start))
+        {
+            // Existential variables like ?x are identifiers too.
+            idx = skip_ident(source, idx + c.len_utf8());
+            let mut qualified = false;
+            while source[idx..].starts_with('.')
+                && char_at(source, idx + 1).map_or(false, is_ident_start)
+            {
+                idx = skip_ident(source, idx + 1);
+                qualified = true;
+            }
+            if qualified {
+                LexemeKind::QualifiedName
+            } else if KEYWORDS.contains(&&source[start..idx]) {
+                LexemeKind::Keyword
+            } else {
+                LexemeKind::Ident
+            }
+        } else {
+            idx += SYMBOLS
+                .iter()
+                .find(|symbol| source[idx..].starts_with(*symbol))
+                .map_or(c.len_utf8(), |symbol| symbol.len());
+            LexemeKind::Symbol
+        };
+        lexemes.push(Lexeme {
+            kind,
+            text: &source[start..idx],
+            start,
+            end: idx,
+        });
+    }
+    lexemes
+}
+
+/// The lexemes of some source, without its comments.
+pub fn lex_code(source: &str) -> Vec<Lexeme<'_>> {
+    lex(source)
+        .into_iter()
+        .filter(|lexeme| lexeme.kind != LexemeKind::Comment)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lex() {
+        let kinds_and_texts = |source| -> Vec<(LexemeKind, &str)> {
+            lex(source)
+                .into_iter()
+                .map(|lexeme| (lexeme.kind, lexeme.text))
+                .collect()
+        };
+        use LexemeKind::*;
+        assert_eq!(
+            kinds_and_texts("∀ x : nat, Nat.add x 0 = x → ?H1 <-> True."),
+            vec![
+                (Symbol, "∀"),
+                (Ident, "x"),
+                (Symbol, ":"),
+                (Ident, "nat"),
+                (Symbol, ","),
+                (QualifiedName, "Nat.add"),
+                (Ident, "x"),
+                (Numeral, "0"),
+                (Symbol, "="),
+                (Ident, "x"),
+                (Symbol, "→"),
+                (Ident, "?H1"),
+                (Symbol, "<->"),
+                (Ident, "True"),
+                (Symbol, "."),
+            ]
+        );
+        assert_eq!(
+            kinds_and_texts("idtac \"(* \"\"x\"\"\" (* a \"*)\" (* b *) *) ; fun"),
+            vec![
+                (Ident, "idtac"),
+                (StringLit, "\"(* \"\"x\"\"\""),
+                (Comment, "(* a \"*)\" (* b *) *)"),
+                (Symbol, ";"),
+                (Keyword, "fun"),
+            ]
+        );
+        let unterminated = lex("auto. (* oops");
+        assert_eq!(unterminated.last().unwrap().kind, Comment);
+        assert_eq!((unterminated[1].start, unterminated[1].end), (4, 5));
+    }
+}
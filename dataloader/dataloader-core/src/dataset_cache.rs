@@ -0,0 +1,259 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::scraped_data::*;
+
+/// Bump this whenever the layout of ScrapedData changes, so that old
+/// caches get rebuilt instead of misread.
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// Identifies the scrape file a cache was built from. Size and mtime
+/// are a cheap check that the file hasn't changed; if they don't match
+/// we fall back on comparing content hashes, so that copying or
+/// touching a scrape doesn't force a rebuild.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+struct CacheHeader {
+    format_version: u32,
+    source_size: u64,
+    source_mtime: (u64, u32),
+    source_hash: [u8; 32],
+    num_items: u64,
+}
+
+pub type ScrapedResultIter = Box<dyn Iterator<Item = Result<ScrapedData, ScrapeError>>>;
+
+/// Reads the scraped data in a file, going through the binary cache in
+/// args.dataset_cache_dir if there is one. The cache is only written
+/// when every line of the scrape parses, so scrapes with bad lines are
+/// always read (and reported on) through the normal reader, which adds
+/// any lines it skips to bad_lines. Failing to write the cache is an
+/// error, rather than a silent slowdown.
+pub fn scraped_from_file_cached(
+    args: &DataloaderArgs,
+    filename: &str,
+    bad_lines: &BadLineCount,
+) -> Result<ScrapedResultIter, ScrapeError> {
+    let cache_dir = match &args.dataset_cache_dir {
+        Some(dir) => dir,
+        None => {
+            return Ok(Box::new(
+                scraped_from_file(filename, args.bad_line_policy())?.counting_into(bad_lines),
+            ))
+        }
+    };
+    let cache_path = cache_path_for(cache_dir, filename);
+    let (size, mtime) = file_stamp(filename).map_err(|err| scrape_io_error(filename, err))?;
+    if let Some(header) = read_cache_header(&cache_path) {
+        if header.source_size == size && header.source_mtime == mtime {
+            if let Some(iter) = open_cache(&cache_path) {
+                return Ok(iter);
+            }
+        } else {
+            let hash = hash_file(filename).map_err(|err| scrape_io_error(filename, err))?;
+            if header.source_hash == hash {
+                // Same contents under a new stamp, so just update the
+                // header rather than reparsing.
+                let new_header = CacheHeader {
+                    source_size: size,
+                    source_mtime: mtime,
+                    ..header
+                };
+                if rewrite_cache_header(&cache_path, &new_header).is_ok() {
+                    if let Some(iter) = open_cache(&cache_path) {
+                        return Ok(iter);
+                    }
+                }
+            }
+        }
+    }
+    build_cache(args, filename, bad_lines, &cache_path, size, mtime)
+}
+
+fn cache_path_for(cache_dir: &str, filename: &str) -> PathBuf {
+    let source_path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+    let path_hash = blake3::hash(source_path.to_string_lossy().as_bytes()).to_hex();
+    let basename = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Path::new(cache_dir).join(format!("{}-{}.cache", basename, &path_hash[..16]))
+}
+
+fn file_stamp(filename: &str) -> io::Result<(u64, (u64, u32))> {
+    let metadata = fs::metadata(filename)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs(), d.subsec_nanos()))
+        .unwrap_or((0, 0));
+    Ok((metadata.len(), mtime))
+}
+
+fn hash_file(filename: &str) -> io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(filename)?, &mut hasher)?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+fn read_cache_header(cache_path: &Path) -> Option<CacheHeader> {
+    let mut reader = BufReader::new(File::open(cache_path).ok()?);
+    let header: CacheHeader = bincode::deserialize_from(&mut reader).ok()?;
+    if header.format_version == CACHE_FORMAT_VERSION {
+        Some(header)
+    } else {
+        None
+    }
+}
+
+fn rewrite_cache_header(cache_path: &Path, header: &CacheHeader) -> bincode::Result<()> {
+    // The header has a fixed size, so it can be overwritten in place.
+    let mut file = fs::OpenOptions::new().write(true).open(cache_path)?;
+    bincode::serialize_into(&mut file, header)
+}
+
+fn open_cache(cache_path: &Path) -> Option<ScrapedResultIter> {
+    let mut reader = BufReader::new(File::open(cache_path).ok()?);
+    let header: CacheHeader = bincode::deserialize_from(&mut reader).ok()?;
+    let cache_name = cache_path.to_string_lossy().into_owned();
+    let mut remaining = header.num_items;
+    Some(Box::new(iter::from_fn(move || {
+        if remaining == 0 {
+            return None;
+        }
+        remaining -= 1;
+        match bincode::deserialize_from(&mut reader) {
+            Ok(datum) => Some(Ok(datum)),
+            Err(err) => {
+                remaining = 0;
+                Some(Err(ScrapeError {
+                    filename: cache_name.clone(),
+                    line_number: 0,
+                    byte_offset: 0,
+                    kind: ScrapeErrorKind::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("corrupt dataset cache, try deleting it: {}", err),
+                    )),
+                }))
+            }
+        }
+    })))
+}
+
+fn build_cache(
+    args: &DataloaderArgs,
+    filename: &str,
+    bad_lines: &BadLineCount,
+    cache_path: &Path,
+    size: u64,
+    mtime: (u64, u32),
+) -> Result<ScrapedResultIter, ScrapeError> {
+    let mut reader = scraped_from_file(filename, args.bad_line_policy())?.counting_into(bad_lines);
+    let data: Vec<ScrapedData> = reader.by_ref().collect::<Result<_, _>>()?;
+    if reader.num_bad_lines() == 0 {
+        let header = CacheHeader {
+            format_version: CACHE_FORMAT_VERSION,
+            source_size: size,
+            source_mtime: mtime,
+            source_hash: hash_file(filename).map_err(|err| scrape_io_error(filename, err))?,
+            num_items: data.len() as u64,
+        };
+        write_cache(cache_path, &header, &data).map_err(|err| {
+            let err = match *err {
+                bincode::ErrorKind::Io(err) => err,
+                err => io::Error::new(io::ErrorKind::Other, err.to_string()),
+            };
+            scrape_io_error(&cache_path.to_string_lossy(), err)
+        })?;
+    }
+    Ok(Box::new(data.into_iter().map(Ok)))
+}
+
+fn write_cache(
+    cache_path: &Path,
+    header: &CacheHeader,
+    data: &[ScrapedData],
+) -> bincode::Result<()> {
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file and move it into place, so that an
+    // interrupted run never leaves a truncated cache behind.
+    let tmp_path = cache_path.with_extension("cache.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    bincode::serialize_into(&mut writer, header)?;
+    for datum in data {
+        bincode::serialize_into(&mut writer, datum)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, cache_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tactics(args: &DataloaderArgs, filename: &str) -> Vec<String> {
+        scraped_tactics_iter(scraped_from_file_cached(args, filename, &Default::default()).unwrap())
+            .map(|t| t.unwrap().tactic)
+            .collect()
+    }
+
+    #[test]
+    fn test_cache_rebuilds_on_change() {
+        let dir =
+            std::env::temp_dir().join(format!("dataloader-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let scrape = dir.join("foo.v.scrape");
+        let scrape = scrape.to_str().unwrap();
+        let mut args: DataloaderArgs = Default::default();
+        args.dataset_cache_dir = Some(dir.join("cache").to_str().unwrap().to_string());
+
+        let tactic_line = |tac: &str| {
+            format!(
+                "{{\"relevant_lemmas\": [], \"prev_tactics\": [], \"prev_goal\": \"True\", \
+                 \"tactic\": \"{}\"}}\n",
+                tac
+            )
+        };
+        fs::write(scrape, tactic_line("auto.")).unwrap();
+        assert_eq!(tactics(&args, scrape), vec!["auto."]);
+        let cache_path = cache_path_for(args.dataset_cache_dir.as_ref().unwrap(), scrape);
+        assert!(cache_path.exists());
+        assert_eq!(tactics(&args, scrape), vec!["auto."]);
+
+        fs::write(scrape, tactic_line("trivial.")).unwrap();
+        assert_eq!(tactics(&args, scrape), vec!["trivial."]);
+
+        // A cache directory inside a file can't be created
+        args.dataset_cache_dir = Some(format!("{}/cache", scrape));
+        assert!(scraped_from_file_cached(&args, scrape, &Default::default()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dataset_cache::{scraped_from_file_cached, ScrapedResultIter};
+use crate::scraped_data::*;
+
+/// A set of scrape files which are loaded together as one dataset.
+pub struct DatasetManifest {
+    pub files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestJson {
+    Files(Vec<String>),
+    ProjectSplit(HashMap<String, Vec<String>>),
+}
+
+/// Manifests are recognized by their extension, since scrape files
+/// are always .scrape (possibly compressed).
+pub fn is_manifest(filename: &str) -> bool {
+    filename.ends_with(".json") || filename.ends_with(".txt")
+}
+
+impl DatasetManifest {
+    /// Loads a manifest, which is one of:
+    ///   * a text file with one source or scrape file per line, like
+    ///     data/compcert-train-files.txt,
+    ///   * a json list of source or scrape files, or
+    ///   * a project split like data/projects-split.json, in which case
+    ///     every scrape file under each project in args.manifest_split
+    ///     is included.
+    /// Relative paths are taken from args.manifest_prelude, or from the
+    /// directory of the manifest if that isn't set, and source files
+    /// (ending in .v) stand for their .v.scrape file.
+    pub fn load(args: &DataloaderArgs, path: &str) -> Result<Self, ScrapeError> {
+        let contents = fs::read_to_string(path).map_err(|err| scrape_io_error(path, err))?;
+        let prelude = match &args.manifest_prelude {
+            Some(prelude) => PathBuf::from(prelude),
+            None => Path::new(path)
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default(),
+        };
+        let entries = if path.ends_with(".json") {
+            let json: ManifestJson = serde_json::from_str(&contents).map_err(|err| ScrapeError {
+                filename: path.to_string(),
+                line_number: err.line(),
+                byte_offset: 0,
+                kind: ScrapeErrorKind::Json(err),
+            })?;
+            match json {
+                ManifestJson::Files(files) => files,
+                ManifestJson::ProjectSplit(mut splits) => {
+                    let split = args.manifest_split.as_deref().unwrap_or("");
+                    let projects = splits.remove(split).ok_or_else(|| {
+                        manifest_error(
+                            path,
+                            format!(
+                                "no split named \"{}\" in project split manifest (set \
+                                 manifest_split to one of {:?})",
+                                split,
+                                splits.keys().collect::<Vec<_>>()
+                            ),
+                        )
+                    })?;
+                    let mut files = Vec::new();
+                    for project in projects {
+                        find_scrape_files(&prelude.join(&project), &mut files)
+                            .map_err(|err| scrape_io_error(path, err))?;
+                    }
+                    files.sort();
+                    return Ok(DatasetManifest { files });
+                }
+            }
+        } else {
+            contents
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect()
+        };
+        Ok(DatasetManifest {
+            files: entries
+                .into_iter()
+                .map(|entry| {
+                    let scrape = if entry.ends_with(".v") {
+                        format!("{}.scrape", entry)
+                    } else {
+                        entry
+                    };
+                    prelude.join(scrape).to_string_lossy().into_owned()
+                })
+                .collect(),
+        })
+    }
+}
+
+fn manifest_error(path: &str, msg: String) -> ScrapeError {
+    scrape_io_error(path, io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+fn find_scrape_files(dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scrape_files(&path, files)?;
+        } else {
+            let name = path.to_string_lossy();
+            if name.ends_with(".scrape")
+                || name.ends_with(".scrape.gz")
+                || name.ends_with(".scrape.zst")
+            {
+                files.push(name.into_owned());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads every file in a manifest, in parallel, and concatenates their
+/// contents in manifest order.
+pub fn scraped_from_manifest(
+    args: &DataloaderArgs,
+    manifest: &DatasetManifest,
+    bad_lines: &BadLineCount,
+) -> Result<Vec<ScrapedData>, ScrapeError> {
+    let per_file: Vec<Vec<ScrapedData>> = manifest
+        .files
+        .par_iter()
+        .map(|filename| -> Result<Vec<ScrapedData>, ScrapeError> {
+            scraped_from_file_cached(args, filename, bad_lines)?.collect()
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(per_file.into_iter().flatten().collect())
+}
+
+/// Reads scraped data from either a single scrape file or a manifest
+/// of them, adding any lines skipped under the bad line policy to
+/// bad_lines.
+pub fn scraped_from_path(
+    args: &DataloaderArgs,
+    filename: &str,
+    bad_lines: &BadLineCount,
+) -> Result<ScrapedResultIter, ScrapeError> {
+    if is_manifest(filename) {
+        let manifest = DatasetManifest::load(args, filename)?;
+        Ok(Box::new(
+            scraped_from_manifest(args, &manifest, bad_lines)?.into_iter().map(Ok),
+        ))
+    } else {
+        scraped_from_file_cached(args, filename, bad_lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_provenance() {
+        let dir =
+            std::env::temp_dir().join(format!("dataloader-manifest-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("proj")).unwrap();
+        let tactic = "{\"relevant_lemmas\": [], \"prev_tactics\": [], \"prev_goal\": \"True\", \
+                      \"tactic\": \"auto.\"}";
+        let lemma = "\"Lemma foo : True.\"";
+        fs::write(dir.join("proj/A.v.scrape"), format!("{}\n{}\n", lemma, tactic)).unwrap();
+        fs::write(
+            dir.join("proj/B.v.scrape"),
+            format!("{}\n{}\n{}\n{}\n", lemma, tactic, lemma, tactic),
+        )
+        .unwrap();
+        fs::write(dir.join("files.txt"), "./proj/A.v\n./proj/B.v\n").unwrap();
+
+        let args: DataloaderArgs = Default::default();
+        let manifest_path = dir.join("files.txt");
+        let provenances: Vec<(String, usize, usize)> = scraped_tactics_iter(
+            scraped_from_path(&args, manifest_path.to_str().unwrap(), &Default::default())
+                .unwrap(),
+        )
+        .map(|tac| {
+            let prov = tac.unwrap().provenance.unwrap();
+            let name = Path::new(&prov.filename)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            (name, prov.line_number, prov.proof_index)
+        })
+        .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            provenances,
+            vec![
+                ("A.v.scrape".to_string(), 2, 0),
+                ("B.v.scrape".to_string(), 2, 0),
+                ("B.v.scrape".to_string(), 4, 1),
+            ]
+        );
+    }
+}
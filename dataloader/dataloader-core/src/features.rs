@@ -26,7 +26,6 @@ use std::collections::{BinaryHeap, HashMap};
 use std::fs::File;
 
 use crate::scraped_data::*;
-use crate::tokenizer::get_symbols;
 use rayon::prelude::*;
 
 use gestalt_ratio::gestalt_ratio;
@@ -66,7 +65,7 @@ pub fn context_features(
         .map(|(score, _datum)| {
             vec![
                 score,
-                // (std::cmp::min(get_symbols(&datum.context.focused_goal()).len(), 100) as f64) / 100.0,
+                // (std::cmp::min(get_words(&datum.context.focused_goal()).len(), 100) as f64) / 100.0,
                 // (std::cmp::min(datum.context.focused_hyps().len(), 20) as f64) / 20.0
             ]
         })
@@ -93,7 +92,7 @@ pub fn sample_context_features(
         hyp_head_feature(tmap, best_hyp),
     ];
     let vec_features = vec![
-        best_score, // (std::cmp::min(get_symbols(&goal).len(), 100) as f64) / 100.0,
+        best_score, // (std::cmp::min(get_words(&goal).len(), 100) as f64) / 100.0,
                    // (std::cmp::min(hypotheses.len(), 20) as f64) / 20.0
     ];
     (word_features, vec_features)
@@ -0,0 +1,155 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use regex::Regex;
+use std::fmt;
+
+/// A hypothesis (or lemma) as Coq prints it, like "H : x = y",
+/// "x, y : nat", or "n := 5 : nat" for a let-bound variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis<'a> {
+    pub names: Vec<&'a str>,
+    pub body: Option<&'a str>,
+    pub ty: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HypothesisError {
+    pub hyp: String,
+    pub msg: &'static str,
+}
+
+impl fmt::Display for HypothesisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in hypothesis \"{}\"", self.msg, self.hyp)
+    }
+}
+
+impl std::error::Error for HypothesisError {}
+
+impl From<HypothesisError> for PyErr {
+    fn from(err: HypothesisError) -> PyErr {
+        exceptions::PyValueError::new_err(err.to_string())
+    }
+}
+
+fn hyp_error(hyp: &str, msg: &'static str) -> HypothesisError {
+    HypothesisError {
+        hyp: hyp.to_string(),
+        msg,
+    }
+}
+
+pub fn parse_hypothesis(hyp: &str) -> Result<Hypothesis<'_>, HypothesisError> {
+    // Names can't contain colons, so the first one always ends them,
+    // whatever the body and type look like.
+    let names_end = hyp.find(':').ok_or_else(|| hyp_error(hyp, "No colon"))?;
+    let names: Vec<&str> = hyp[..names_end].split(',').map(|name| name.trim()).collect();
+    if names
+        .iter()
+        .any(|name| name.is_empty() || name.contains(char::is_whitespace))
+    {
+        return Err(hyp_error(hyp, "Malformed names"));
+    }
+    let rest = &hyp[names_end..];
+    if rest.starts_with(":=") {
+        let body_and_type = &rest[2..];
+        let type_colon = find_type_colon(body_and_type)
+            .ok_or_else(|| hyp_error(hyp, "No type for let-bound variable"))?;
+        Ok(Hypothesis {
+            names,
+            body: Some(body_and_type[..type_colon].trim()),
+            ty: body_and_type[type_colon + 1..].trim(),
+        })
+    } else {
+        Ok(Hypothesis {
+            names,
+            body: None,
+            ty: rest[1..].trim(),
+        })
+    }
+}
+
+/// Finds the colon separating the body of a let-bound hypothesis
+/// from its type, skipping over the colons of binders and of
+/// anything in parentheses, brackets or braces.
+fn find_type_colon(body_and_type: &str) -> Option<usize> {
+    lazy_static! {
+        static ref TOKEN: Regex = Regex::new(r"[\w']+|:=|=>|::|\S").unwrap();
+    }
+    let mut depth = 0;
+    let mut binder_depths: Vec<i32> = Vec::new();
+    for token in TOKEN.find_iter(body_and_type) {
+        match token.as_str() {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            "fun" | "forall" | "exists" | "exists2" | "let" => binder_depths.push(depth),
+            "=>" | "," | ":=" if binder_depths.last() == Some(&depth) => {
+                binder_depths.pop();
+            }
+            ":" if depth == 0 && binder_depths.is_empty() => return Some(token.start()),
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hypothesis() {
+        assert_eq!(
+            parse_hypothesis("H : x = y").unwrap(),
+            Hypothesis {
+                names: vec!["H"],
+                body: None,
+                ty: "x = y"
+            }
+        );
+        assert_eq!(
+            parse_hypothesis("x, y : nat").unwrap().names,
+            vec!["x", "y"]
+        );
+        assert_eq!(
+            parse_hypothesis("H:forall n : nat, n = n").unwrap().ty,
+            "forall n : nat, n = n"
+        );
+        assert_eq!(
+            parse_hypothesis("f := fun x : nat => (x : nat) : nat -> nat").unwrap(),
+            Hypothesis {
+                names: vec!["f"],
+                body: Some("fun x : nat => (x : nat)"),
+                ty: "nat -> nat"
+            }
+        );
+        assert_eq!(
+            parse_hypothesis("l := a :: nil : list nat").unwrap().ty,
+            "list nat"
+        );
+        assert!(parse_hypothesis("x = y").is_err());
+        assert!(parse_hypothesis("n := 5").is_err());
+        assert!(parse_hypothesis("H H' : P").is_err());
+    }
+}
@@ -19,27 +19,54 @@
 //
 /* *********************************************************************** */
 
-use pyo3::exceptions;
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
-use std::fs::File;
 
+mod binders;
+mod command_kind;
+mod compression;
+mod context_delta;
 mod context_filter;
 mod context_filter_ast;
+mod coq_lexer;
+mod dataset_cache;
+mod dataset_manifest;
 mod features;
+mod hypothesis;
+mod linearize;
 mod models;
 mod paren_util;
+mod preprocess_config;
 mod scraped_data;
+mod tactic_analysis;
+mod tactic_ast;
+mod tactic_signature;
 mod tokenizer;
+use command_kind::CommandKind;
+use context_delta::*;
 use context_filter::*;
+use dataset_manifest::scraped_from_path;
 use features::*;
 use models::features_dnn_evaluator::*;
 use models::features_polyarg_predictor::*;
 use models::goal_enc_evaluator::*;
+use preprocess_config::PreprocessConfig;
 use paren_util::parse_sexp_one_level;
 use scraped_data::*;
 use tokenizer::get_words;
 
+/// What the benchmarks in benches/ use from the crate.
+#[doc(hidden)]
+pub mod bench_support {
+    pub use crate::context_filter::{apply_filter, parse_filter};
+    pub use crate::context_filter_ast::ContextFilterAST;
+    pub use crate::scraped_data::{
+        scraped_from_file, scraped_tactics_iter, BadLinePolicy, DataloaderArgs, Obligation,
+        ProofContext, ScrapedTactic,
+    };
+    pub use crate::tactic_signature::TacticSignatures;
+}
+
 #[macro_use]
 extern crate lazy_static;
 
@@ -93,7 +120,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
             LongTensor2D,
             FloatTensor2D,
             LongTensor1D,
-            LongTensor1D,
+            LongTensor2D,
         ),
         (Vec<i64>, i64),
     )> {
@@ -116,7 +143,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
             LongTensor2D,
             FloatTensor2D,
             LongTensor1D,
-            LongTensor1D,
+            LongTensor2D,
         ),
         (Vec<i64>, i64),
     )> {
@@ -128,7 +155,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         args: DataloaderArgs,
         metadata: PickleableFPAMetadata,
         context_batch: Vec<TacticContext>,
-    ) -> (
+    ) -> PyResult<(
         LongUnpaddedTensor3D,
         FloatUnpaddedTensor3D,
         LongTensor1D,
@@ -136,7 +163,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         BoolTensor2D,
         LongTensor2D,
         FloatTensor2D,
-    ) {
+    )> {
         sample_fpa_batch(args, metadata, context_batch)
     }
     #[pyfn(m, "sample_fpa")]
@@ -148,7 +175,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         prev_tactics: Vec<String>,
         hypotheses: Vec<String>,
         goal: String,
-    ) -> (
+    ) -> PyResult<(
         LongUnpaddedTensor3D,
         FloatUnpaddedTensor3D,
         LongTensor1D,
@@ -156,7 +183,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         BoolTensor2D,
         LongTensor2D,
         FloatTensor2D,
-    ) {
+    )> {
         sample_fpa(
             args,
             metadata,
@@ -175,15 +202,28 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         goal: &str,
         tac_idx: i64,
         arg_idx: i64,
-    ) -> String {
+    ) -> PyResult<String> {
         decode_fpa_result(args, metadata, hyps, goal, tac_idx, arg_idx)
     }
+    #[pyfn(m, "decode_fpa_multiarg_result")]
+    fn decode_fpa_multiarg_result_py(
+        _py: Python,
+        args: DataloaderArgs,
+        metadata: PickleableFPAMetadata,
+        hyps: Vec<String>,
+        goal: &str,
+        tac_idx: i64,
+        arg_idxs: Vec<i64>,
+        arg_spans: Vec<Option<PickleableTermSpan>>,
+    ) -> PyResult<String> {
+        decode_fpa_multiarg_result(args, metadata, hyps, goal, tac_idx, arg_idxs, arg_spans)
+    }
     #[pyfn(m, "tokenize")]
     fn tokenize_fpa_py(
         _py: Python,
         args: DataloaderArgs,
         metadata: PickleableFPAMetadata,
-        term: String) -> LongTensor1D {
+        term: String) -> PyResult<LongTensor1D> {
         tokenize_fpa(args, metadata, term)
     }
     #[pyfn(m, "get_premise_features")]
@@ -206,7 +246,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         args: DataloaderArgs,
         metadata: PickleableFPAMetadata,
         tac_idx: i64,
-    ) -> String {
+    ) -> PyResult<String> {
         decode_fpa_stem(&args, metadata, tac_idx)
     }
     #[pyfn(m, "encode_fpa_stem")]
@@ -215,50 +255,76 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         args: DataloaderArgs,
         metadata: PickleableFPAMetadata,
         tac_stem: String,
-    ) -> i64 {
+    ) -> PyResult<i64> {
         encode_fpa_stem(&args, metadata, tac_stem)
     }
+    #[pyfn(m, "preprocess_fpa_tactic")]
+    fn preprocess_fpa_tactic_py(
+        _py: Python,
+        metadata: PickleableFPAMetadata,
+        goal: &str,
+        tactic: &str,
+    ) -> PyResult<String> {
+        let config = PreprocessConfig::from_pickleable(&metadata.3)?;
+        Ok(preprocess_tactic(&config, tactic, goal))
+    }
     #[pyfn(m, "decode_fpa_arg")]
     fn decode_fpa_arg_py(
         _py: Python,
         args: DataloaderArgs,
-        _metadata: PickleableFPAMetadata,
+        metadata: PickleableFPAMetadata,
         hyps: Vec<String>,
         goal: &str,
         arg_idx: i64,
-    ) -> String {
-        decode_fpa_arg(&args, hyps, goal, arg_idx)
+    ) -> PyResult<String> {
+        let (_indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+        Ok(decode_fpa_arg(&args, &tokenizer, &hyps, goal, arg_idx)?)
     }
     #[pyfn(m, "encode_fpa_arg")]
     fn encode_fpa_arg_py(
         _py: Python,
         args: DataloaderArgs,
-        _metadata: PickleableFPAMetadata,
+        metadata: PickleableFPAMetadata,
         hyps: Vec<String>,
         goal: &str,
         arg: &str,
-    ) -> i64 {
-        encode_fpa_arg_unbounded(&args, hyps, goal, arg)
+    ) -> PyResult<i64> {
+        let (_indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+        Ok(encode_fpa_arg_unbounded(&args, &tokenizer, hyps, goal, arg)?)
+    }
+    #[pyfn(m, "encode_fpa_tactic")]
+    fn encode_fpa_tactic_py(
+        _py: Python,
+        args: DataloaderArgs,
+        metadata: PickleableFPAMetadata,
+        hyps: Vec<String>,
+        goal: &str,
+        tactic: &str,
+    ) -> PyResult<(i64, Vec<i64>, Vec<Option<PickleableTermSpan>>)> {
+        encode_fpa_tactic(&args, metadata, hyps, goal, tactic)
     }
     #[pyfn(m, "get_num_tokens")]
-    fn get_num_tokens(_py: Python, metadata: PickleableFPAMetadata) -> i64 {
-        let (_indexer, tokenizer, _ftmap) = fpa_metadata_from_pickleable(metadata);
-        tokenizer.num_tokens()
+    fn get_num_tokens(_py: Python, metadata: PickleableFPAMetadata) -> PyResult<i64> {
+        let (_indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+        Ok(tokenizer.num_tokens())
     }
     #[pyfn(m, "fpa_get_num_possible_args")]
     fn get_num_possible_args(_py: Python, args: DataloaderArgs) -> i64 {
         fpa_get_num_possible_args(&args)
     }
     #[pyfn(m, "get_num_indices")]
-    fn get_num_indices(_py: Python, metadata: PickleableFPAMetadata) -> i64 {
-        let (mut indexer, _tokenizer, _ftmap) = fpa_metadata_from_pickleable(metadata);
+    fn get_num_indices(_py: Python, metadata: PickleableFPAMetadata) -> PyResult<i64> {
+        let (mut indexer, _tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
         indexer.freeze();
-        indexer.num_indices()
+        Ok(indexer.num_indices())
     }
     #[pyfn(m, "get_word_feature_vocab_sizes")]
-    fn get_word_feature_vocab_sizes(_py: Python, metadata: PickleableFPAMetadata) -> Vec<i64> {
-        let (_indexer, _tokenizer, ftmap) = fpa_metadata_from_pickleable(metadata);
-        ftmap.word_features_sizes()
+    fn get_word_feature_vocab_sizes(
+        _py: Python,
+        metadata: PickleableFPAMetadata,
+    ) -> PyResult<Vec<i64>> {
+        let (_indexer, _tokenizer, ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+        Ok(ftmap.word_features_sizes())
     }
     #[pyfn(m, "get_vec_features_size")]
     fn get_vec_features_size(_py: Python, _metadata: PickleableFPAMetadata) -> i64 {
@@ -275,10 +341,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         args: DataloaderArgs,
         filename: String,
     ) -> PyResult<(GoalEncMetadata, LongTensor2D, FloatTensor1D)> {
-        py.allow_threads(move || {
-            Ok(goals_to_total_distances_tensors(args, filename, None)
-                .map_err(|err| exceptions::PyValueError::new_err(err))?)
-        })
+        py.allow_threads(move || Ok(goals_to_total_distances_tensors(args, filename, None)?))
     }
     #[pyfn(m, "goals_to_total_distances_tensors_with_meta")]
     fn _goals_to_total_distances_tensors_with_meta(
@@ -289,8 +352,7 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
     ) -> PyResult<(LongTensor2D, FloatTensor1D)> {
         py.allow_threads(move || {
             let (_, goals, outputs) =
-                goals_to_total_distances_tensors(args, filename, Some(metadata))
-                    .map_err(|err| exceptions::PyValueError::new_err(err))?;
+                goals_to_total_distances_tensors(args, filename, Some(metadata))?;
             Ok((goals, outputs))
         })
     }
@@ -313,18 +375,45 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         filename: String,
         num_tactics: Option<usize>,
     ) -> PyResult<Vec<ScrapedTactic>> {
-        let iter = scraped_from_file(
-            File::open(filename)
-                .map_err(|_err| exceptions::PyValueError::new_err("Failed to open file"))?,
-        )
-        .flat_map(|datum| match datum {
-            ScrapedData::Vernac(_) => None,
-            ScrapedData::Tactic(t) => Some(t),
-        });
-        match num_tactics {
-            Some(num) => Ok(iter.take(num).collect()),
-            None => Ok(iter.collect()),
-        }
+        let bad_lines = BadLineCount::default();
+        let iter =
+            scraped_tactics_iter(scraped_from_path(&Default::default(), &filename, &bad_lines)?);
+        let tactics = match num_tactics {
+            Some(num) => iter.take(num).collect::<Result<_, _>>()?,
+            None => iter.collect::<Result<_, _>>()?,
+        };
+        bad_lines.warn_if_any(&filename)?;
+        Ok(tactics)
+    }
+
+    #[pyfn(m, "command_kind")]
+    fn _command_kind(_py: Python, command: &str) -> CommandKind {
+        command_kind::command_kind(command)
+    }
+    #[pyfn(m, "context_delta")]
+    fn _context_delta(
+        _py: Python,
+        before: &ProofContext,
+        after: &ProofContext,
+    ) -> ContextDelta {
+        context_delta(before, after)
+    }
+
+    #[pyfn(m, "scraped_proofs_from_file")]
+    fn _scraped_proofs_from_file(
+        _py: Python,
+        args: &DataloaderArgs,
+        filename: String,
+        num_proofs: Option<usize>,
+    ) -> PyResult<Vec<ScrapedProof>> {
+        let bad_lines = BadLineCount::default();
+        let iter = scraped_proofs_iter(scraped_from_path(args, &filename, &bad_lines)?);
+        let proofs = match num_proofs {
+            Some(num) => iter.take(num).collect::<Result<_, _>>()?,
+            None => iter.collect::<Result<_, _>>()?,
+        };
+        bad_lines.warn_if_any(&filename)?;
+        Ok(proofs)
     }
 
     #[pyfn(m, "tactic_transitions_from_file")]
@@ -334,15 +423,68 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
         filename: String,
         num_tactics: usize,
     ) -> PyResult<Vec<ScrapedTransition>> {
-        let filter = parse_filter(&args.context_filter);
-        let raw_iter = scraped_from_file(
-            File::open(filename)
-                .map_err(|_err| exceptions::PyValueError::new_err("Failed to open file"))?,
-        );
-        let transition_iter = scraped_transition_iter(raw_iter);
-        let filtered_iter = transition_iter
-            .filter(|transition| apply_filter(args, &filter, &transition.scraped_before()));
-        Ok(filtered_iter.take(num_tactics).collect::<Vec<_>>())
+        let filter = parse_filter(&args.context_filter)?;
+        let config = args.load_preprocess_config()?;
+        let signatures = args.load_tactic_signatures()?;
+        let bad_lines = BadLineCount::default();
+        let raw_iter = scraped_from_path(args, &filename, &bad_lines)?;
+        let transition_iter = scraped_transition_iter(&config, raw_iter);
+        let filtered_iter = transition_iter.filter(|transition| match transition {
+            Ok(transition) => apply_transition_filter(args, &signatures, &filter, transition),
+            Err(_) => true,
+        });
+        let transitions = filtered_iter
+            .take(num_tactics)
+            .collect::<Result<Vec<_>, _>>()?;
+        bad_lines.warn_if_any(&filename)?;
+        check_py_predicates(&filter)?;
+        Ok(transitions)
+    }
+    #[pyfn(m, "explain_filter")]
+    fn _explain_filter(
+        py: Python,
+        args: &DataloaderArgs,
+        filename: String,
+        num_examples: usize,
+    ) -> PyResult<FilterExplanation> {
+        let filter = parse_filter(&args.context_filter)?;
+        let config = args.load_preprocess_config()?;
+        let signatures = args.load_tactic_signatures()?;
+        let bad_lines = BadLineCount::default();
+        let raw_data = scraped_from_path(args, &filename, &bad_lines)?;
+        // Only read transitions when the filter needs them, since they
+        // split up some tactics.
+        let raw_iter: Box<dyn Iterator<Item = Result<_, ScrapeError>>> =
+            if filter.uses_transitions() {
+                Box::new(scraped_transition_iter(&config, raw_data).map(|transition| {
+                    transition.map(|transition| {
+                        (transition.scraped_before(), Some(transition.after))
+                    })
+                }))
+            } else {
+                Box::new(
+                    preprocessed_tactics_iter(&config, raw_data)
+                        .map(|datum| datum.map(|datum| (datum, None))),
+                )
+            };
+        let data: Vec<(ScrapedTactic, Option<ProofContext>)> = match args.max_tuples {
+            Some(max) => raw_iter.take(max).collect::<Result<_, _>>()?,
+            None => raw_iter.collect::<Result<_, _>>()?,
+        };
+        bad_lines.warn_if_any(&filename)?;
+        let explanation = py.allow_threads(|| {
+            explain_filter(args, &signatures, &filter, &data, num_examples)
+        });
+        check_py_predicates(&filter)?;
+        Ok(explanation)
+    }
+    #[pyfn(m, "format_filter_explanation")]
+    fn _format_filter_explanation(_py: Python, explanation: FilterExplanation) -> String {
+        explanation.to_string()
+    }
+    #[pyfn(m, "register_filter_predicate")]
+    fn _register_filter_predicate(_py: Python, name: &str, predicate: PyObject) -> PyResult<()> {
+        register_py_predicate(name, predicate)
     }
 
     #[pyfunction]
@@ -393,6 +535,9 @@ fn dataloader(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<ScrapedTactic>()?;
     m.add_class::<ProofContext>()?;
     m.add_class::<ScrapedTransition>()?;
+    m.add_class::<ScrapedProof>()?;
+    m.add_class::<ContextDelta>()?;
+    m.add_class::<Provenance>()?;
     m.add_class::<Obligation>()?;
     m.add_class::<TacticContext>()?;
     Ok(())
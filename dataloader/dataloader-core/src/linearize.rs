@@ -0,0 +1,253 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::scraped_data::ProofContext;
+use crate::tactic_ast::{parse_tactic, TacticAST};
+
+/// A tactic which couldn't be linearized, because it uses a
+/// combinator we can't see through or because the goal counts don't
+/// pin down how many times each part of it ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearizeError {
+    pub tactic: String,
+    pub msg: String,
+}
+
+impl fmt::Display for LinearizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't linearize \"{}\": {}", self.tactic, self.msg)
+    }
+}
+
+impl std::error::Error for LinearizeError {}
+
+/// How many goals a tactic replaced the goal it ran on with, going by
+/// the open goals before and after it. Tactics which shelve or give
+/// up on goals are left alone, since those goals leave the count.
+pub fn goals_created(before: &ProofContext, after: &ProofContext) -> Option<usize> {
+    let open_before = before.fg_goals.len() + before.bg_goals.len();
+    let open_after = after.fg_goals.len() + after.bg_goals.len();
+    if open_before == 0
+        || before.shelved_goals.len() != after.shelved_goals.len()
+        || before.given_up_goals.len() != after.given_up_goals.len()
+    {
+        return None;
+    }
+    (open_after + 1).checked_sub(open_before)
+}
+
+/// Expands a tactic that chains with ";" into the tactics it runs on
+/// each goal, in an order that can be run one by one. Each goal a
+/// tactic creates gets its own copy of whatever follows the ";", run
+/// last-goal-first with goal selectors so that earlier goals keep
+/// their numbers. The number of goals each part of the chain creates
+/// comes from "[ | ]" dispatch, from tactics whose goal count is
+/// always the same, and from goals_created, the number of goals the
+/// whole tactic created. Tactics without a ";" come back unchanged.
+pub fn linearize_tactic(
+    tactic: &str,
+    goals_created: Option<usize>,
+) -> Result<Vec<String>, LinearizeError> {
+    let ast = match parse_tactic(tactic) {
+        Ok(ast) => ast,
+        Err(err) if tactic.contains(';') => {
+            return Err(LinearizeError {
+                tactic: tactic.to_string(),
+                msg: err.msg,
+            })
+        }
+        Err(_) => return Ok(vec![tactic.trim().to_string()]),
+    };
+    if !ast.has_sequencing() {
+        return Ok(vec![tactic.trim().to_string()]);
+    }
+    expand(&ast, 1, goals_created)
+        .map(|(commands, _)| commands)
+        .map_err(|msg| LinearizeError {
+            tactic: tactic.to_string(),
+            msg,
+        })
+}
+
+// The commands to run a tactic on a particular goal, and the number of
+// goals it creates if we know it.
+type Expansion = (Vec<String>, Option<usize>);
+
+fn expand(tactic: &TacticAST, goal: usize, total: Option<usize>) -> Result<Expansion, String> {
+    let (commands, fanout) = match tactic {
+        TacticAST::Then(first, second) => {
+            let second_fanout = expand(second, 1, None).ok().and_then(|(_, f)| f);
+            let first_total = match (total, second_fanout) {
+                (Some(t), Some(r)) if r > 0 && t % r == 0 => Some(t / r),
+                _ => None,
+            };
+            let (mut commands, first_fanout) = expand(first, goal, first_total)?;
+            let num_goals = first_fanout
+                .ok_or_else(|| format!("can't tell how many goals \"{}\" creates", first))?;
+            let second_total = if num_goals == 1 { total } else { None };
+            let mut fanout = Some(0);
+            for idx in (goal..goal + num_goals).rev() {
+                let (second_commands, f) = expand(second, idx, second_total)?;
+                commands.extend(second_commands);
+                fanout = fanout.and_then(|acc| f.map(|f| acc + f));
+            }
+            (commands, fanout)
+        }
+        TacticAST::ThenBranches(first, branches) => {
+            let (mut commands, _) = expand(first, goal, Some(branches.len()))?;
+            let mut expansions: Vec<Result<Expansion, String>> = branches
+                .iter()
+                .enumerate()
+                .map(|(idx, branch)| expand(branch, goal + idx, None))
+                .collect();
+            // If the goal counts of all but one branch are known, the
+            // total tells us the last one.
+            let unknown: Vec<usize> = expansions
+                .iter()
+                .enumerate()
+                .filter(|(_, expansion)| !matches!(expansion, Ok((_, Some(_)))))
+                .map(|(idx, _)| idx)
+                .collect();
+            if let (Some(t), [idx]) = (total, unknown.as_slice()) {
+                let known: usize = expansions
+                    .iter()
+                    .filter_map(|expansion| match expansion {
+                        Ok((_, Some(f))) => Some(*f),
+                        _ => None,
+                    })
+                    .sum();
+                if let Some(remaining) = t.checked_sub(known) {
+                    expansions[*idx] = expand(&branches[*idx], goal + idx, Some(remaining));
+                }
+            }
+            let mut fanout = Some(0);
+            for expansion in expansions.into_iter().rev() {
+                let (branch_commands, f) = expansion?;
+                commands.extend(branch_commands);
+                fanout = fanout.and_then(|acc| f.map(|f| acc + f));
+            }
+            (commands, fanout)
+        }
+        TacticAST::Select(Some(selected), body) if goal == 1 => {
+            return expand(body, *selected, total)
+        }
+        _ if tactic.has_sequencing() => {
+            return Err(format!("can't see through \"{}\"", tactic));
+        }
+        _ => {
+            let command = if goal == 1 {
+                format!("{}.", tactic)
+            } else {
+                format!("{}: {}.", goal, tactic)
+            };
+            (vec![command], known_fanout(tactic))
+        }
+    };
+    match (total, fanout) {
+        (Some(t), Some(f)) if t != f => Err(format!(
+            "\"{}\" should create {} goals, but created {}",
+            tactic, f, t
+        )),
+        _ => Ok((commands, total.or(fanout))),
+    }
+}
+
+/// The number of goals a tactic without a ";" creates, for tactics
+/// where that never changes. These either leave a single goal or
+/// fail, or else solve the goal or fail.
+fn known_fanout(tactic: &TacticAST) -> Option<usize> {
+    lazy_static! {
+        static ref FANOUTS: HashMap<&'static str, usize> = {
+            let mut res = HashMap::new();
+            for stem in &[
+                "intros", "intro", "simpl", "cbn", "cbv", "compute", "unfold", "fold", "red",
+                "hnf", "subst", "clear", "revert", "generalize", "rename", "move", "set", "pose",
+                "remember", "pattern", "symmetry", "exfalso", "left", "right", "exists",
+                "eexists", "idtac",
+            ] {
+                res.insert(*stem, 1);
+            }
+            for stem in &[
+                "reflexivity", "assumption", "exact", "eexact", "lia", "omega", "lra", "nia",
+                "congruence", "discriminate", "contradiction", "tauto", "easy", "ring",
+            ] {
+                res.insert(*stem, 0);
+            }
+            res
+        };
+    }
+    match tactic {
+        TacticAST::Atomic(atomic) => FANOUTS.get(atomic.stem.as_str()).cloned(),
+        TacticAST::Now(_) | TacticAST::Solve(_) => Some(0),
+        TacticAST::Progress(body) => known_fanout(body),
+        TacticAST::Try(body) | TacticAST::Repeat(body) | TacticAST::Do(_, body) => {
+            match known_fanout(body) {
+                Some(1) => Some(1),
+                _ => None,
+            }
+        }
+        TacticAST::OrElse(first, second) => match (known_fanout(first), known_fanout(second)) {
+            (Some(f1), Some(f2)) if f1 == f2 => Some(f1),
+            _ => None,
+        },
+        TacticAST::First(branches) => {
+            let fanouts: Option<Vec<usize>> = branches.iter().map(known_fanout).collect();
+            match fanouts?.as_slice() {
+                [f, rest @ ..] if rest.iter().all(|r| r == f) => Some(*f),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linearize_tactic() {
+        assert_eq!(
+            linearize_tactic("intros; simpl.", None).unwrap(),
+            vec!["intros.", "simpl."]
+        );
+        assert_eq!(
+            linearize_tactic("split; [auto | lia].", Some(0)).unwrap(),
+            vec!["split.", "2: lia.", "auto."]
+        );
+        assert_eq!(
+            linearize_tactic("induction n; simpl.", Some(2)).unwrap(),
+            vec!["induction n.", "2: simpl.", "simpl."]
+        );
+        assert_eq!(
+            linearize_tactic("destruct H as [x|y]; [ | right; auto ].", Some(1)).unwrap(),
+            vec!["destruct H as [x|y].", "2: right.", "2: auto.", "idtac."]
+        );
+        assert_eq!(linearize_tactic("auto.", None).unwrap(), vec!["auto."]);
+        assert!(linearize_tactic("induction n; simpl; auto.", Some(0)).is_err());
+        assert!(linearize_tactic("destruct H; auto.", Some(0)).is_err());
+        assert!(linearize_tactic("try (split; auto).", Some(0)).is_err());
+        assert!(linearize_tactic("intros; lia.", Some(1)).is_err());
+    }
+}
@@ -19,8 +19,13 @@
 //
 /* *********************************************************************** */
 
+use pyo3::prelude::*;
+
+use crate::command_kind::{command_kind, CommandKind, ProofEndKind};
 use crate::scraped_data::*;
 use std::cmp::min;
+use std::convert::Infallible;
+use std::fmt;
 
 pub fn normalize_distances(max_distance: usize, distances: Vec<usize>) -> Vec<f64> {
     distances
@@ -29,113 +34,302 @@ pub fn normalize_distances(max_distance: usize, distances: Vec<usize>) -> Vec<f6
         .collect()
 }
 
-pub fn tactic_distances(scraped_data: Vec<ScrapedData>) -> Vec<(ScrapedTactic, usize)> {
-    let mut in_proof = false;
-    let mut interaction_buffer = Vec::new();
-    let mut blocks = Vec::new();
-
-    for interaction in scraped_data {
-        match interaction {
-            ScrapedData::Tactic(tac) => {
-                if !in_proof {
-                    interaction_buffer.clear();
-                    in_proof = true;
+/// A proof that can't be given distance labels, because it wasn't
+/// finished or its structure doesn't make sense.
+#[derive(Debug, Clone)]
+pub struct UnlabelableProof {
+    pub statement: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for UnlabelableProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.statement {
+            Some(statement) => write!(f, "{}: {}", statement.trim(), self.reason),
+            None => write!(f, "<no statement>: {}", self.reason),
+        }
+    }
+}
+
+/// Labels each tactic with its distance to the end of its proof,
+/// skipping the proofs that can't be labelled, and raising a python
+/// UserWarning that lists them.
+pub fn tactic_distances(scraped_data: Vec<ScrapedData>) -> PyResult<Vec<(ScrapedTactic, usize)>> {
+    let (labelled, unlabelable) = label_distances(scraped_data);
+    if unlabelable.len() > 0 {
+        let proofs: Vec<String> = unlabelable.iter().map(|proof| proof.to_string()).collect();
+        let message = format!(
+            "Couldn't label distances for {} proofs:\n{}",
+            proofs.len(),
+            proofs.join("\n")
+        );
+        warn_py(&message)?;
+    }
+    Ok(labelled)
+}
+
+/// Labels each tactic with its distance to the end of its proof, and
+/// returns the proofs that couldn't be labelled separately. Like the
+/// scrapes themselves, a proof only counts once a vernac follows it, so
+/// a proof at the very end of the data is never labelled, even if it
+/// ends in a Qed.
+pub fn label_distances(
+    scraped_data: Vec<ScrapedData>,
+) -> (Vec<(ScrapedTactic, usize)>, Vec<UnlabelableProof>) {
+    let ends_in_proof = matches!(scraped_data.last(), Some(ScrapedData::Tactic(_)));
+    let mut labelled = Vec::new();
+    let mut unlabelable = Vec::new();
+    let mut proofs = scraped_proofs_iter(scraped_data.into_iter().map(Ok::<_, Infallible>))
+        .peekable();
+    while let Some(proof) = proofs.next() {
+        let proof = match proof {
+            Ok(proof) => proof,
+            Err(never) => match never {},
+        };
+        let statement = proof.statement.clone();
+        if ends_in_proof && proofs.peek().is_none() {
+            unlabelable.push(UnlabelableProof {
+                statement,
+                reason: "the data ends inside the proof".to_string(),
+            });
+            continue;
+        }
+        match label_proof_distances(proof) {
+            Ok(mut proof_labelled) => labelled.append(&mut proof_labelled),
+            Err(reason) => unlabelable.push(UnlabelableProof { statement, reason }),
+        }
+    }
+    (labelled, unlabelable)
+}
+
+#[derive(PartialEq)]
+enum FocusOpener {
+    Root,
+    Brace,
+    Bullet(usize),
+}
+
+/// A focused goal, and the tactics run in it so far. Tactics in
+/// nested foci are already labelled, and their count is in
+/// closed_distance.
+struct Focus {
+    opener: FocusOpener,
+    segment: Vec<ScrapedTactic>,
+    closed_distance: usize,
+    finished: Vec<(ScrapedTactic, usize)>,
+}
+
+impl Focus {
+    fn new(opener: FocusOpener) -> Self {
+        Focus {
+            opener,
+            segment: Vec::new(),
+            closed_distance: 0,
+            finished: Vec::new(),
+        }
+    }
+}
+
+/// Labels the tactics in the focus on top of the stack, and moves
+/// them to the focus under it, or into done if it was the last one.
+fn close_focus(foci: &mut Vec<Focus>, done: &mut Vec<(ScrapedTactic, usize)>) {
+    let focus = foci.pop().expect("Not enough foci");
+    let segment_len = focus.segment.len();
+    let mut closed_tacs: Vec<(ScrapedTactic, usize)> = focus
+        .segment
+        .into_iter()
+        .rev()
+        .zip((1 + focus.closed_distance)..)
+        .collect::<Vec<(ScrapedTactic, usize)>>()
+        .into_iter()
+        .rev()
+        .collect();
+    let mut already_closed_tacs = focus.finished;
+    match foci.last_mut() {
+        Some(parent) => {
+            parent.finished.append(&mut closed_tacs);
+            parent.finished.append(&mut already_closed_tacs);
+            parent.closed_distance += focus.closed_distance + segment_len;
+        }
+        None => {
+            done.append(&mut closed_tacs);
+            done.append(&mut already_closed_tacs);
+        }
+    }
+}
+
+/// Closes the foci left at the end of a proof. Bullets don't need to
+/// be closed explicitly, but braces do.
+fn close_all_foci(
+    foci: &mut Vec<Focus>,
+    done: &mut Vec<(ScrapedTactic, usize)>,
+) -> Result<(), String> {
+    while let Some(focus) = foci.last() {
+        if focus.opener == FocusOpener::Brace {
+            return Err("a { is never closed".to_string());
+        }
+        close_focus(foci, done);
+    }
+    Ok(())
+}
+
+/// Whether a bullet command has a tactic after the bullet, like "- auto."
+fn bullet_has_tactic(command: &str) -> bool {
+    kill_comments(command)
+        .trim()
+        .trim_start_matches(|c| "-+*".contains(c))
+        .trim()
+        != ""
+}
+
+/// Labels the tactics of a proof with their distance to the end of
+/// the goal they were run on, counting the tactics run on any
+/// subgoals they focused on along the way. Braces, bullets and goal
+/// selectors focus goals. Proofs which were admitted, aborted or
+/// never finished can't be labelled.
+fn label_proof_distances(proof: ScrapedProof) -> Result<Vec<(ScrapedTactic, usize)>, String> {
+    let closing_kind = match proof.tactics.last().map(|tactic| tactic.command_kind()) {
+        Some(CommandKind::ProofEnd(kind)) => Some(kind),
+        _ => proof
+            .closing_command
+            .as_ref()
+            .and_then(|command| match command_kind(command) {
+                CommandKind::ProofEnd(kind) => Some(kind),
+                _ => None,
+            }),
+    };
+    match closing_kind {
+        None => return Err("the proof isn't finished".to_string()),
+        Some(ProofEndKind::Admitted) => return Err("the proof was admitted".to_string()),
+        Some(ProofEndKind::Abort) => return Err("the proof was aborted".to_string()),
+        Some(_) => (),
+    }
+
+    let mut foci = vec![Focus::new(FocusOpener::Root)];
+    let mut done = Vec::new();
+    for interaction in proof.tactics.into_iter() {
+        let kind = match interaction.command_kind() {
+            CommandKind::GoalSelector(_, inner) => *inner,
+            kind => kind,
+        };
+        match kind {
+            CommandKind::OpenBrace => foci.push(Focus::new(FocusOpener::Brace)),
+            CommandKind::CloseBrace => {
+                while foci.last().map(|focus| &focus.opener) != Some(&FocusOpener::Brace) {
+                    if foci.len() == 1 {
+                        return Err("a } doesn't match any {".to_string());
+                    }
+                    close_focus(&mut foci, &mut done);
                 }
-                interaction_buffer.push(tac)
+                close_focus(&mut foci, &mut done);
             }
-            ScrapedData::Vernac(_cmd) => {
-                if in_proof {
-                    blocks.push(interaction_buffer.clone());
-                    in_proof = false;
+            CommandKind::Bullet(level) => {
+                // A bullet ends the goal of the last bullet at its
+                // level (and anything nested in it), if there was one
+                // since the last brace.
+                let last_opener = foci.iter().rposition(|focus| {
+                    focus.opener == FocusOpener::Bullet(level) || focus.opener == FocusOpener::Brace
+                });
+                if let Some(idx) = last_opener {
+                    if foci[idx].opener == FocusOpener::Bullet(level) {
+                        while foci.len() > idx {
+                            close_focus(&mut foci, &mut done);
+                        }
+                    }
+                }
+                foci.push(Focus::new(FocusOpener::Bullet(level)));
+                if bullet_has_tactic(&interaction.tactic) {
+                    foci.last_mut().unwrap().segment.push(interaction);
                 }
             }
+            CommandKind::ProofEnd(_) => {
+                close_all_foci(&mut foci, &mut done)?;
+                done.push((interaction, 0));
+                return Ok(done);
+            }
+            CommandKind::Tactic | CommandKind::ProofStart | CommandKind::GoalSelector(..) => {
+                foci.last_mut().unwrap().segment.push(interaction)
+            }
         }
     }
+    // The proof was closed by a vernac after the tactics
+    close_all_foci(&mut foci, &mut done)?;
+    Ok(done)
+}
 
-    let mut result = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for block in blocks {
-        let mut distanced_block: Vec<(ScrapedTactic, usize)> = label_block_distances(block);
-        result.append(&mut distanced_block);
+    fn vernac(command: &str) -> ScrapedData {
+        ScrapedData::Vernac(VernacCommand {
+            command: command.to_string(),
+        })
     }
-    return result;
-}
 
-fn label_block_distances(block: Vec<ScrapedTactic>) -> Vec<(ScrapedTactic, usize)> {
-    let mut path_segments: Vec<Vec<ScrapedTactic>> = vec![Vec::new()];
-    let mut closed_distances: Vec<usize> = vec![0, 0];
-    let mut finished_segments: Vec<Vec<(ScrapedTactic, usize)>> = vec![Vec::new(), Vec::new()];
-
-    let close_goal =
-        |path_segments: &mut Vec<Vec<ScrapedTactic>>,
-         closed_distances: &mut Vec<usize>,
-         finished_segments: &mut Vec<Vec<(ScrapedTactic, usize)>>| {
-            let last_segment = path_segments.pop().expect("Not enough path segments");
-            let last_segment_len = last_segment.len();
-            let last_distance = closed_distances.pop().expect("Not enough closed distances");
-            let mut closed_tacs: Vec<(ScrapedTactic, usize)> = last_segment
-                .into_iter()
-                .rev()
-                .zip((1 + last_distance)..)
-                .collect::<Vec<(ScrapedTactic, usize)>>()
-                .into_iter()
-                .rev()
-                .collect();
-
-            let mut already_closed_tacs = finished_segments
-                .pop()
-                .expect("Not enough finished segments");
-            let last_finished_segment = finished_segments
-                .last_mut()
-                .expect("Not enough finished segments");
-            last_finished_segment.append(&mut closed_tacs);
-            last_finished_segment.append(&mut already_closed_tacs);
-            let next_last_distance = closed_distances
-                .last_mut()
-                .expect("Not enough closed distances");
-            *next_last_distance += last_distance + last_segment_len
-        };
+    fn proof(tactics: &[&str]) -> Vec<ScrapedData> {
+        let mut data = vec![vernac(&format!("Lemma foo{} : True.", tactics.len()))];
+        data.extend(tactics.iter().map(|tactic| {
+            ScrapedData::Tactic(ScrapedTactic {
+                relevant_lemmas: vec![],
+                prev_tactics: vec![],
+                context: ProofContext::empty(),
+                tactic: tactic.to_string(),
+                provenance: None,
+            })
+        }));
+        data
+    }
 
-    for interaction in block.into_iter() {
-        let trimmed_tac = interaction.tactic.trim();
-        if trimmed_tac == "{" {
-            path_segments.push(Vec::new());
-            closed_distances.push(0);
-            finished_segments.push(Vec::new());
-        } else if trimmed_tac == "}" {
-            close_goal(
-                &mut path_segments,
-                &mut closed_distances,
-                &mut finished_segments,
-            );
-        } else if trimmed_tac == "Qed." {
-            close_goal(
-                &mut path_segments,
-                &mut closed_distances,
-                &mut finished_segments,
-            );
-            {
-                let last_finished_segment = finished_segments
-                    .last_mut()
-                    .expect("Not enougn finished segments");
-                last_finished_segment.push((interaction, 0));
-            }
-            return finished_segments.pop().unwrap();
-        } else {
-            let last_path_segment = path_segments
-                .last_mut()
-                .expect("Not enougn finished segments");
-            last_path_segment.push(interaction);
+    fn distances(tactics: &[&str]) -> Result<Vec<(String, usize)>, String> {
+        let mut data = proof(tactics);
+        data.push(vernac("Lemma bar : True."));
+        let (mut labelled, unlabelable) = label_distances(data);
+        if let Some(proof) = unlabelable.first() {
+            return Err(proof.reason.clone());
         }
+        // Labels come out grouped by focus, so put them back in order
+        labelled.sort_by_key(|(tactic, _distance)| {
+            tactics.iter().position(|t| *t == tactic.tactic).unwrap()
+        });
+        Ok(labelled
+            .into_iter()
+            .map(|(tactic, distance)| (tactic.tactic, distance))
+            .collect())
+    }
+
+    #[test]
+    fn test_label_distances() {
+        let expected = |labels: &[(&str, usize)]| -> Result<Vec<(String, usize)>, String> {
+            Ok(labels.iter().map(|(t, d)| (t.to_string(), *d)).collect())
+        };
+        assert_eq!(
+            distances(&["Proof.", "split.", "{", "auto.", "}", "simpl.", "Qed. "]),
+            expected(&[("Proof.", 4), ("split.", 3), ("auto.", 1), ("simpl.", 2), ("Qed. ", 0)])
+        );
+        assert_eq!(
+            distances(&["split.", "-", "auto.", "- simpl.", "+ lia.", "+ lia.", "Defined."]),
+            expected(&[
+                ("split.", 5),
+                ("auto.", 1),
+                ("- simpl.", 3),
+                ("+ lia.", 1),
+                ("+ lia.", 1),
+                ("Defined.", 0)
+            ])
+        );
+        assert_eq!(
+            distances(&["split.", "auto.", "Admitted."]),
+            Err("the proof was admitted".to_string())
+        );
+        assert_eq!(distances(&["split.", "auto."]), Err("the proof isn't finished".to_string()));
+        assert_eq!(
+            distances(&["split.", "{", "auto.", "Qed."]),
+            Err("a { is never closed".to_string())
+        );
+        // Without a vernac after it, the last proof is left out
+        let (labelled, unlabelable) = label_distances(proof(&["auto.", "Qed."]));
+        assert!(labelled.is_empty());
+        assert_eq!(unlabelable[0].reason, "the data ends inside the proof");
     }
-    assert_eq!(path_segments.len(), 1);
-    close_goal(
-        &mut path_segments,
-        &mut closed_distances,
-        &mut finished_segments,
-    );
-    assert_eq!(finished_segments.len(), 1);
-    finished_segments
-        .pop()
-        .expect("Not enough finished segments")
 }
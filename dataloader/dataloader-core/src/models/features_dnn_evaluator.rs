@@ -19,13 +19,11 @@
 //
 /* *********************************************************************** */
 
-use pyo3::exceptions;
 use pyo3::prelude::*;
 
-use std::fs::File;
-
 use crate::models::evaluator_common::*;
 use crate::features::{context_features, TokenMap, VEC_FEATURES_SIZE};
+use crate::dataset_manifest::scraped_from_path;
 use crate::scraped_data::*;
 
 pub fn features_to_total_distances_tensors(
@@ -40,34 +38,29 @@ pub fn features_to_total_distances_tensors(
     Vec<i64>,
     i64,
 )> {
-    match File::open(filename) {
-        Result::Ok(file) => {
-            let scraped = scraped_from_file(file).collect();
-            let distanced = tactic_distances(scraped);
-            let (tactics, distances): (Vec<ScrapedTactic>, Vec<usize>) =
-                distanced.into_iter().unzip();
-            let outputs = normalize_distances(args.max_distance, distances)
-                .into_iter()
-                .map(|distance| vec![distance])
-                .collect();
-            let tmap = match map {
-                Some(m) => m,
-                None => TokenMap::initialize(&tactics, args.num_keywords),
-            };
-            let (word_features, float_features) = context_features(&args, &tmap, &tactics);
-            let word_features_sizes = tmap.word_features_sizes();
+    let bad_lines = BadLineCount::default();
+    let scraped = scraped_from_path(&args, &filename, &bad_lines)?.collect::<Result<_, _>>()?;
+    bad_lines.warn_if_any(&filename)?;
+    let distanced = tactic_distances(scraped)?;
+    let (tactics, distances): (Vec<ScrapedTactic>, Vec<usize>) =
+        distanced.into_iter().unzip();
+    let outputs = normalize_distances(args.max_distance, distances)
+        .into_iter()
+        .map(|distance| vec![distance])
+        .collect();
+    let tmap = match map {
+        Some(m) => m,
+        None => TokenMap::initialize(&tactics, args.num_keywords),
+    };
+    let (word_features, float_features) = context_features(&args, &tmap, &tactics);
+    let word_features_sizes = tmap.word_features_sizes();
 
-            Ok((
-                tmap,
-                word_features,
-                float_features,
-                outputs,
-                word_features_sizes,
-                VEC_FEATURES_SIZE,
-            ))
-        }
-        Result::Err(_err) => Err(exceptions::PyValueError::new_err(
-            "Failed to open file",
-        )),
-    }
+    Ok((
+        tmap,
+        word_features,
+        float_features,
+        outputs,
+        word_features_sizes,
+        VEC_FEATURES_SIZE,
+    ))
 }
@@ -6,17 +6,22 @@ use rand::Rng;
 use rayon::prelude::*;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use std::fmt;
 
-use crate::context_filter::{filter_data, parse_filter, apply_filter};
+use crate::context_filter::{filter_data, parse_tactic_filter};
+use crate::coq_lexer::{lex_code, LexemeKind};
 use crate::features::PickleableTokenMap as PickleableFeaturesTokenMap;
 use crate::features::TokenMap as FeaturesTokenMap;
 use crate::features::*;
+use crate::hypothesis::{parse_hypothesis, HypothesisError};
 use crate::paren_util::split_to_next_matching_paren_or_space;
+use crate::preprocess_config::PreprocessConfig;
+use crate::dataset_manifest::scraped_from_path;
 use crate::scraped_data::*;
+use crate::tactic_signature::TacticSignatures;
 use crate::tokenizer::{
-    get_words, normalize_sentence_length, OpenIndexer, PickleableIndexer, PickleableTokenizer,
-    Token, Tokenizer,
+    get_word_ranges, get_words, normalize_sentence_length, AlignedTokens, OpenIndexer,
+    PickleableIndexer, PickleableTokenizer, Token, Tokenizer,
 };
 use gestalt_ratio::gestalt_ratio;
 
@@ -26,6 +31,64 @@ pub enum TacticArgument {
     GoalToken(usize),
     NoArg,
     Unrecognized,
+    /// A term found in the goal or a premise's type, as a half-open
+    /// range of word positions there
+    TermSpan(TermSource, usize, usize),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TermSource {
+    Goal,
+    Premise(usize),
+}
+
+/// A term span as python sees it, where a source of None means the
+/// goal.
+pub type PickleableTermSpan = (Option<usize>, usize, usize);
+
+/// Tactics whose arguments aren't just a list of goal tokens and
+/// premises are predicted as a stem with the rest of the argument
+/// string baked in, with a hole wherever an encoded argument goes,
+/// like "apply $ with $".
+const ARG_HOLE: &str = "$";
+
+/// Words in argument strings which are never goal tokens or premises.
+const CLAUSE_KEYWORDS: &[&str] = &[
+    "with", "as", "in", "at", "by", "using", "until", "into", "eqn", "fun", "forall",
+];
+
+#[derive(Debug)]
+pub enum ArgEncodingError {
+    BadPremise(HypothesisError),
+    Unencodable(String),
+}
+
+impl fmt::Display for ArgEncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgEncodingError::BadPremise(err) => write!(f, "{}", err),
+            ArgEncodingError::Unencodable(arg) => write!(
+                f,
+                "\"{}\" can't be encoded as a single argument, \
+                 encode the whole tactic with encode_fpa_tactic instead",
+                arg
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArgEncodingError {}
+
+impl From<HypothesisError> for ArgEncodingError {
+    fn from(err: HypothesisError) -> Self {
+        ArgEncodingError::BadPremise(err)
+    }
+}
+
+impl From<ArgEncodingError> for PyErr {
+    fn from(err: ArgEncodingError) -> PyErr {
+        exceptions::PyValueError::new_err(err.to_string())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -43,27 +106,57 @@ pub struct FPAOutput {
     argument: TacticArgument,
 }
 
-pub type FPAMetadata = (OpenIndexer<String>, Tokenizer, FeaturesTokenMap);
-pub type PickleableFPAMetadata = (
-    PickleableIndexer<String>,
-    PickleableTokenizer,
-    PickleableFeaturesTokenMap,
+// The preprocessing config is part of the metadata so that tactics at
+// prediction time are preprocessed the same way as in training.
+pub type FPAMetadata = (OpenIndexer<String>, Tokenizer, FeaturesTokenMap, PreprocessConfig);
+#[derive(Clone)]
+pub struct PickleableFPAMetadata(
+    pub PickleableIndexer<String>,
+    pub PickleableTokenizer,
+    pub PickleableFeaturesTokenMap,
+    pub String,
 );
 
+/// In python, metadata is a tuple of its four fields. Models pickled
+/// before the preprocessing config was recorded have only the first
+/// three, and get the default config.
+impl<'source> FromPyObject<'source> for PickleableFPAMetadata {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        if let Ok((indexer, tokenizer, tmap, config)) = obj.extract() {
+            return Ok(PickleableFPAMetadata(indexer, tokenizer, tmap, config));
+        }
+        let (indexer, tokenizer, tmap) = obj.extract()?;
+        Ok(PickleableFPAMetadata(
+            indexer,
+            tokenizer,
+            tmap,
+            PreprocessConfig::default().to_pickleable(),
+        ))
+    }
+}
+
+impl IntoPy<PyObject> for PickleableFPAMetadata {
+    fn into_py(self, py: Python) -> PyObject {
+        (self.0, self.1, self.2, self.3).into_py(py)
+    }
+}
+
 pub fn fpa_metadata_to_pickleable(metadata: FPAMetadata) -> PickleableFPAMetadata {
-    (
+    PickleableFPAMetadata(
         metadata.0.to_pickleable(),
         metadata.1.to_pickleable(),
         metadata.2.to_dicts(),
+        metadata.3.to_pickleable(),
     )
 }
 
-pub fn fpa_metadata_from_pickleable(pick: PickleableFPAMetadata) -> FPAMetadata {
-    (
+pub fn fpa_metadata_from_pickleable(pick: PickleableFPAMetadata) -> PyResult<FPAMetadata> {
+    Ok((
         OpenIndexer::from_pickleable(pick.0),
         Tokenizer::from_pickleable(pick.1),
         FeaturesTokenMap::from_dicts(pick.2),
-    )
+        PreprocessConfig::from_pickleable(&pick.3)?,
+    ))
 }
 
 pub fn features_polyarg_tensors(
@@ -81,32 +174,36 @@ pub fn features_polyarg_tensors(
         LongTensor2D,
         FloatTensor2D,
         LongTensor1D,
-        LongTensor1D,
+        LongTensor2D,
     ),
     (Vec<i64>, i64),
 )> {
-    let filter = parse_filter(&args.context_filter);
-    let raw_data_iter = scraped_from_file(
-        File::open(filename)
-            .map_err(|_err| exceptions::PyValueError::new_err("Failed to open file")
-            )?)
-        .flat_map(|datum| match datum {
-            ScrapedData::Vernac(_) => None,
-            ScrapedData::Tactic(t) => Some(t),
-        })
-        .map(preprocess_datum)
-        .filter(|datum| apply_filter(&args, &filter, datum));
-    let mut raw_data: Vec<ScrapedTactic> = match args.max_tuples {
-        Some(max) => raw_data_iter.take(max).collect(),
-        None => raw_data_iter.collect(),
+    let filter = parse_tactic_filter(&args.context_filter)?;
+    let preprocess_config = match &metadata {
+        Some(PickleableFPAMetadata(_, _, _, config)) => PreprocessConfig::from_pickleable(config)?,
+        None => args.load_preprocess_config()?,
     };
+    let signatures = args.load_tactic_signatures()?;
+    let bad_lines = BadLineCount::default();
+    let preprocessed_data = preprocessed_tactics_iter(
+        &preprocess_config,
+        scraped_from_path(&args, &filename, &bad_lines)?,
+    )
+    .collect::<Result<_, _>>()?;
+    bad_lines.warn_if_any(&filename)?;
+    // This runs with the GIL released, so python predicates in the
+    // filter can run on every worker.
+    let mut raw_data = filter_data(&args, &signatures, &filter, preprocessed_data)?;
+    if let Some(max) = args.max_tuples {
+        raw_data.truncate(max);
+    }
 
     scraped_to_file(
-        File::create("filtered-data.json").unwrap(),
+        "filtered-data.json",
         raw_data.iter().cloned().map(ScrapedData::Tactic),
-    );
+    )?;
     let (mut indexer, rest_meta) = match metadata {
-        Some((indexer, tokenizer, tmap)) => (
+        Some(PickleableFPAMetadata(indexer, tokenizer, tmap, _config)) => (
             OpenIndexer::from_pickleable(indexer),
             Some((tokenizer, tmap)),
         ),
@@ -141,15 +238,18 @@ pub fn features_polyarg_tensors(
     };
     raw_data.sort_by_key(|pnt| -(pnt.context.focused_hyps().len() as i64));
 
-    let tactic_stem_indices: Vec<i64> = raw_data
-        .iter()
-        .map(|data| {
-            indexer.lookup(
-                get_stem(&data.tactic)
-                    .expect(&format!("Couldn't get the stem for {}", data.tactic))
-                    .to_string(),
-            )
-        })
+    let (stems, (arguments, selected_prems)): (Vec<String>, (Vec<_>, Vec<Vec<&String>>)) =
+        raw_data
+            .par_iter()
+            .map(|scraped| {
+                let (stem, arguments, selected) =
+                    get_arguments(&args, &signatures, &tokenizer, scraped);
+                (stem, (arguments, selected))
+            })
+            .unzip();
+    let tactic_stem_indices: Vec<i64> = stems
+        .into_iter()
+        .map(|stem| indexer.lookup(stem))
         .collect();
     // indexer.freeze();
 
@@ -174,27 +274,14 @@ pub fn features_polyarg_tensors(
         .map(|prems| prems.len() as i64)
         .collect();
     let (word_features, vec_features) = context_features(&args, &features_token_map, &raw_data);
-    let tokenized_goals: Vec<_> = raw_data
+    let (tokenized_goals, goal_symbols_mask): (Vec<_>, Vec<_>) = raw_data
         .par_iter()
-        .map(|tac| {
-            normalize_sentence_length(
-                tokenizer.tokenize(&tac.context.focused_goal()),
-                args.max_length,
-                0,
-            )
-        })
-        .collect();
-    let goal_symbols_mask = raw_data
-        .par_iter()
-        .map(|scraped| get_goal_mask(&scraped.context.focused_goal(), args.max_length))
-        .collect();
-    let (arg_indices, selected_prems): (Vec<i64>, Vec<Vec<&String>>) = raw_data
-        .par_iter()
-        .map(|scraped| {
-            let (arg, selected) = get_argument(&args, scraped);
-            (arg_to_index(&args, arg), selected)
-        })
+        .map(|tac| goal_tensors(&tokenizer, tac.context.focused_goal(), args.max_length))
         .unzip();
+    let arg_indices: Vec<Vec<i64>> = arguments
+        .into_iter()
+        .map(|arguments| args_to_indices(&args, arguments))
+        .collect();
     let tokenized_hyps: Vec<Vec<Vec<i64>>> = selected_prems
         .par_iter()
         .map(|hyps| {
@@ -230,7 +317,7 @@ pub fn features_polyarg_tensors(
         .collect();
     let word_features_sizes = features_token_map.word_features_sizes();
     Ok((
-        fpa_metadata_to_pickleable((indexer, tokenizer, features_token_map)),
+        fpa_metadata_to_pickleable((indexer, tokenizer, features_token_map, preprocess_config)),
         (
             tokenized_hyps,
             hyp_features,
@@ -248,43 +335,51 @@ pub fn features_polyarg_tensors(
 
 /// This function is for debugging purposes
 #[allow(dead_code)]
-pub fn lookup_hyp(premises: Vec<String>, hyp_name: &str) -> String {
-    let hyp_idx = indexed_premises(premises.iter().map(|s| s.as_ref()))
-        .into_iter()
-        .find(|(_idx, hname)| *hname == hyp_name)
-        .expect(&format!("Couldn't find a hyp with name {}", hyp_name))
-        .0;
-    premises[hyp_idx].clone()
+pub fn lookup_hyp(premises: Vec<String>, hyp_name: &str) -> Option<String> {
+    premises.into_iter().find(|premise| match parse_hypothesis(premise) {
+        Ok(hyp) => hyp.names.contains(&hyp_name),
+        Err(_) => false,
+    })
 }
 
-fn get_goal_mask(goal: &str, max_length: usize) -> Vec<bool> {
-    lazy_static! {
-        static ref STARTS_WITH_LETTER: Regex =
-            Regex::new(r"^\w.*").expect("Couldn't compile regex");
+fn is_arg_word(kind: LexemeKind) -> bool {
+    match kind {
+        LexemeKind::Ident | LexemeKind::QualifiedName | LexemeKind::Numeral => true,
+        _ => false,
     }
+}
 
-    let words = get_words(goal);
-    let mut mask_vec: Vec<_> = words
-        .into_iter()
+/// The goal tokens the model sees, and the mask of which of them can
+/// be arguments. Goal argument indices are positions in the same
+/// token sequence, offset by one for the no-argument entry at the
+/// front of the mask.
+fn goal_tensors(tokenizer: &Tokenizer, goal: &str, max_length: usize) -> (LongTensor1D, Vec<bool>) {
+    let aligned = tokenizer.tokenize_aligned(goal);
+    let mut mask_vec: Vec<bool> = aligned
+        .token_to_word
+        .iter()
         .take(max_length)
-        .map(|goal_word| STARTS_WITH_LETTER.is_match(goal_word))
+        .map(|word_idx| is_arg_word(aligned.words[*word_idx].kind))
         .collect();
     if mask_vec.len() < max_length {
         mask_vec.extend([false].repeat(max_length - mask_vec.len()));
     }
     mask_vec.insert(0, true);
-    mask_vec
+    (
+        normalize_sentence_length(aligned.tokens, max_length, 0),
+        mask_vec,
+    )
 }
 
 pub fn tokenize_fpa(
     args: DataloaderArgs,
     metadata: PickleableFPAMetadata,
-    term: String) -> LongTensor1D {
+    term: String) -> PyResult<LongTensor1D> {
 
-    let (_indexer, tokenizer, _ftmap) = fpa_metadata_from_pickleable(metadata);
-    normalize_sentence_length(
+    let (_indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+    Ok(normalize_sentence_length(
         tokenizer.tokenize(&term),
-        args.max_length, 0)
+        args.max_length, 0))
 }
 
 pub fn get_premise_features(
@@ -306,7 +401,7 @@ pub fn sample_fpa_batch(
     args: DataloaderArgs,
     metadata: PickleableFPAMetadata,
     context_batch: Vec<TacticContext>,
-) -> (
+) -> PyResult<(
     LongUnpaddedTensor3D,
     FloatUnpaddedTensor3D,
     LongTensor1D,
@@ -314,8 +409,8 @@ pub fn sample_fpa_batch(
     BoolTensor2D,
     LongTensor2D,
     FloatTensor2D,
-) {
-    let (_indexer, tokenizer, ftmap) = fpa_metadata_from_pickleable(metadata);
+)> {
+    let (_indexer, tokenizer, ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
     let (word_features_batch, vec_features_batch) = context_batch
         .iter()
         .map(|ctxt| {
@@ -363,20 +458,10 @@ pub fn sample_fpa_batch(
         })
         .collect();
 
-    let tgoals_batch = context_batch
+    let (tgoals_batch, goal_symbols_mask) = context_batch
         .par_iter()
-        .map(|ctxt| {
-            normalize_sentence_length(
-                tokenizer.tokenize(&ctxt.obligation.goal),
-                args.max_length,
-                0,
-            )
-        })
-        .collect();
-    let goal_symbols_mask = context_batch
-        .par_iter()
-        .map(|ctxt| get_goal_mask(&ctxt.obligation.goal, args.max_length))
-        .collect();
+        .map(|ctxt| goal_tensors(&tokenizer, &ctxt.obligation.goal, args.max_length))
+        .unzip();
     let tprems_batch: Vec<Vec<Vec<i64>>> = premises_batch
         .into_iter()
         .map(|premises| {
@@ -398,7 +483,7 @@ pub fn sample_fpa_batch(
         .map(|tprems| tprems.len() as i64)
         .collect();
 
-    (
+    Ok((
         tprems_batch,
         premise_features_batch,
         num_hyps_batch,
@@ -406,7 +491,7 @@ pub fn sample_fpa_batch(
         goal_symbols_mask,
         word_features_batch,
         vec_features_batch,
-    )
+    ))
 }
 
 pub fn sample_fpa(
@@ -416,7 +501,7 @@ pub fn sample_fpa(
     prev_tactics: Vec<String>,
     hypotheses: Vec<String>,
     goal: String,
-) -> (
+) -> PyResult<(
     LongUnpaddedTensor3D,
     FloatUnpaddedTensor3D,
     LongTensor1D,
@@ -424,8 +509,8 @@ pub fn sample_fpa(
     BoolTensor2D,
     LongTensor2D,
     FloatTensor2D,
-) {
-    let (_indexer, tokenizer, ftmap) = fpa_metadata_from_pickleable(metadata);
+)> {
+    let (_indexer, tokenizer, ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
     let (word_features, vec_features) = sample_context_features(
         &args,
         &ftmap,
@@ -444,9 +529,7 @@ pub fn sample_fpa(
         .zip(premise_scores.iter())
         .map(|(premise, score)| vec![*score, equality_hyp_feature(premise, &goal)])
         .collect();
-    let tokenized_goal = normalize_sentence_length(tokenizer.tokenize(&goal), args.max_length, 0);
-
-    let goal_symbols_mask = get_goal_mask(&goal, args.max_length);
+    let (tokenized_goal, goal_symbols_mask) = goal_tensors(&tokenizer, &goal, args.max_length);
 
     let tokenized_premises: Vec<Vec<i64>> = all_premises
         .into_iter()
@@ -459,7 +542,7 @@ pub fn sample_fpa(
         })
         .collect();
     let num_hyps = tokenized_premises.len();
-    (
+    Ok((
         vec![tokenized_premises],
         vec![premise_features],
         vec![num_hyps as i64],
@@ -467,7 +550,7 @@ pub fn sample_fpa(
         vec![goal_symbols_mask],
         vec![word_features],
         vec![vec_features],
-    )
+    ))
 }
 
 pub fn decode_fpa_result(
@@ -477,13 +560,84 @@ pub fn decode_fpa_result(
     goal: &str,
     tac_idx: i64,
     arg_idx: i64,
-) -> String {
-    let stem = decode_fpa_stem(&args, metadata, tac_idx);
-    let arg = decode_fpa_arg(&args, premises, goal, arg_idx);
-    if arg == "" {
-        format!("{}.", stem)
+) -> PyResult<String> {
+    decode_fpa_multiarg_result(
+        args,
+        metadata,
+        premises,
+        goal,
+        tac_idx,
+        vec![arg_idx],
+        vec![None],
+    )
+}
+
+/// Decodes a stem and a sequence of arguments, as encode_fpa_tactic
+/// produces them, back into a tactic. Each argument is a term span if
+/// there is one for it, and an index otherwise. Padding arguments
+/// decode to nothing.
+pub fn decode_fpa_multiarg_result(
+    args: DataloaderArgs,
+    metadata: PickleableFPAMetadata,
+    premises: Vec<String>,
+    goal: &str,
+    tac_idx: i64,
+    arg_idxs: Vec<i64>,
+    arg_spans: Vec<Option<PickleableTermSpan>>,
+) -> PyResult<String> {
+    let (indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+    let stem = indexer.reverse_lookup(tac_idx);
+    let decoded_args = arg_idxs
+        .into_iter()
+        .enumerate()
+        .map(|(idx, arg_idx)| match arg_spans.get(idx).cloned().flatten() {
+            Some(span) => Ok(decode_fpa_term_span(&premises, goal, span)),
+            None => decode_fpa_arg(&args, &tokenizer, &premises, goal, arg_idx),
+        })
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(fill_arg_holes(&stem, &decoded_args))
+}
+
+/// Reconstructs the text of a term span, with its whitespace
+/// normalized.
+pub fn decode_fpa_term_span(premises: &[String], goal: &str, span: PickleableTermSpan) -> String {
+    let (source, start, end) = span;
+    let text = match source {
+        None => goal,
+        Some(idx) if idx < premises.len() => get_hyp_type(&premises[idx]),
+        Some(_) => return "<INVALID>".to_string(),
+    };
+    let word_ranges = get_word_ranges(text);
+    if start >= end || end > word_ranges.len() {
+        return "<INVALID>".to_string();
+    }
+    text[word_ranges[start].0..word_ranges[end - 1].1]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn fill_arg_holes(stem: &str, decoded_args: &[String]) -> String {
+    if stem.contains(ARG_HOLE) {
+        let mut pieces = stem.split(ARG_HOLE);
+        let mut result = pieces.next().unwrap_or("").to_string();
+        for (idx, piece) in pieces.enumerate() {
+            result.push_str(decoded_args.get(idx).map_or("", |arg| arg.as_str()));
+            result.push_str(piece);
+        }
+        result.push('.');
+        result
     } else {
-        format!("{} {}.", stem, arg)
+        let present_args: Vec<&str> = decoded_args
+            .iter()
+            .map(|arg| arg.as_str())
+            .filter(|arg| *arg != "")
+            .collect();
+        if present_args.len() == 0 {
+            format!("{}.", stem)
+        } else {
+            format!("{} {}.", stem, present_args.join(" "))
+        }
     }
 }
 
@@ -491,26 +645,29 @@ pub fn decode_fpa_stem(
     _args: &DataloaderArgs,
     metadata: PickleableFPAMetadata,
     tac_idx: i64,
-) -> String {
-    let (indexer, _tokenizer, _ftmap) = fpa_metadata_from_pickleable(metadata);
-    indexer.reverse_lookup(tac_idx)
+) -> PyResult<String> {
+    let (indexer, _tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+    Ok(indexer.reverse_lookup(tac_idx))
 }
 
 pub fn encode_fpa_stem(
     _args: &DataloaderArgs,
     metadata: PickleableFPAMetadata,
     tac_stem: String,
-) -> i64 {
-    let (mut indexer, _tokenizer, _ftmap) = fpa_metadata_from_pickleable(metadata);
-    indexer.lookup(tac_stem)
+) -> PyResult<i64> {
+    let (mut indexer, _tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+    Ok(indexer.lookup(tac_stem))
 }
 
+/// Decodes an argument index. Goal indices are positions in the
+/// goal's tokens, as goal_tensors produces them.
 pub fn decode_fpa_arg(
     args: &DataloaderArgs,
-    premises: Vec<String>,
+    tokenizer: &Tokenizer,
+    premises: &[String],
     goal: &str,
     arg_idx: i64,
-) -> String {
+) -> Result<String, HypothesisError> {
     let argtype = if arg_idx == 0 {
         TacticArgument::NoArg
     } else if (arg_idx as usize) <= args.max_length {
@@ -518,31 +675,19 @@ pub fn decode_fpa_arg(
     } else {
         TacticArgument::HypVar((arg_idx as usize) - args.max_length - 1)
     };
-    match argtype {
+    Ok(match argtype {
         TacticArgument::NoArg => "".to_string(),
         TacticArgument::Unrecognized => "".to_string(),
-        TacticArgument::GoalToken(tidx) => {
-            // assert!(tidx < get_words(goal).len(), format!("{}, {:?}, {}", goal, get_words(goal), tidx));
-            if tidx >= get_words(goal).len() {
-                "<INVALID>".to_string()
-            } else {
-                get_words(goal)[tidx].to_string()
-            }
-        }
+        TacticArgument::TermSpan(..) => "".to_string(),
+        TacticArgument::GoalToken(tidx) => match tokenizer.tokenize_aligned(goal).token_word(tidx) {
+            Some(word) => word.text.to_string(),
+            None => "<INVALID>".to_string(),
+        },
         TacticArgument::HypVar(hidx) => {
             assert!(hidx < premises.len());
-            let all_vars = premises[hidx]
-                .split(":")
-                .next()
-                .expect("No colon in hyp")
-                .trim();
-            if all_vars.contains(",") {
-                all_vars.split(",").next().unwrap().to_string()
-            } else {
-                all_vars.to_string()
-            }
+            parse_hypothesis(&premises[hidx])?.names[0].to_string()
         }
-    }
+    })
 }
 
 fn equality_hyp_feature(hyp: &str, goal: &str) -> f64 {
@@ -578,50 +723,274 @@ pub fn fpa_get_num_possible_args(
 
 pub fn encode_fpa_arg_unbounded(
     args: &DataloaderArgs,
+    tokenizer: &Tokenizer,
     hyps: Vec<String>,
     goal: &str,
     arg: &str,
-) -> i64 {
-    let argstr_tokens: Vec<&str> = arg[..arg.len() - 1].split_whitespace().collect();
-    if argstr_tokens.len() == 0 {
-        arg_to_index(args, TacticArgument::NoArg)
-    } else if argstr_tokens.len() > 1 {
-        panic!("A multi argument tactic made it past the context filter!");
-    } else {
-        let goal_symbols = get_words(goal);
-        let arg_token = argstr_tokens[0];
-        match goal_symbols
-            .into_iter()
+) -> Result<i64, ArgEncodingError> {
+    let single_arg_args = DataloaderArgs {
+        max_arity: 1,
+        ..args.clone()
+    };
+    let context = ArgContext::new(
+        tokenizer,
+        goal,
+        hyps.iter().map(|s| s.as_ref()).collect(),
+        hyps.len(),
+    )?;
+    let (template, arguments) =
+        encode_arguments(&single_arg_args, &TacticSignatures::default(), "", arg, &context);
+    match (template.as_str(), arguments.first()) {
+        ("", None) => Ok(arg_to_index(args, TacticArgument::NoArg)),
+        (ARG_HOLE, Some(argument)) => Ok(arg_to_index(args, argument.clone())),
+        _ => Err(ArgEncodingError::Unencodable(arg.to_string())),
+    }
+}
+
+/// Encodes a whole tactic as a stem index, its argument indices, and
+/// the term spans of any arguments which are terms, both padded to
+/// the maximum arity. Premise indices are into hyps. This is the only
+/// encoding with term spans; features_polyarg_tensors leaves them out.
+pub fn encode_fpa_tactic(
+    args: &DataloaderArgs,
+    metadata: PickleableFPAMetadata,
+    hyps: Vec<String>,
+    goal: &str,
+    tactic: &str,
+) -> PyResult<(i64, Vec<i64>, Vec<Option<PickleableTermSpan>>)> {
+    let (mut indexer, tokenizer, _ftmap, _config) = fpa_metadata_from_pickleable(metadata)?;
+    let signatures = args.load_tactic_signatures()?;
+    let (tactic_stem, tactic_argstr) =
+        split_tactic(tactic).ok_or_else(|| ArgEncodingError::Unencodable(tactic.to_string()))?;
+    let mut context = ArgContext::new(
+        &tokenizer,
+        goal,
+        hyps.iter().map(|s| s.as_ref()).collect(),
+        hyps.len(),
+    )
+    .map_err(ArgEncodingError::from)?;
+    context.term_spans = true;
+    let (stem, arguments) =
+        encode_arguments(args, &signatures, &tactic_stem, &tactic_argstr, &context);
+    let spans = args_to_spans(args, &arguments);
+    Ok((indexer.lookup(stem), args_to_indices(args, arguments), spans))
+}
+
+/// What the arguments of a tactic can refer to.
+struct ArgContext<'a> {
+    goal_tokens: AlignedTokens<'a>,
+    premises: Vec<&'a str>,
+    premise_names: Vec<(usize, String)>,
+    max_distinct_premises: usize,
+    /// Whether to look for parenthesized terms in the goal and
+    /// premises. The training tensors only have room for argument
+    /// indices, so this is off there.
+    term_spans: bool,
+}
+
+impl<'a> ArgContext<'a> {
+    fn new(
+        tokenizer: &Tokenizer,
+        goal: &'a str,
+        premises: Vec<&'a str>,
+        max_distinct_premises: usize,
+    ) -> Result<Self, HypothesisError> {
+        let premise_names = indexed_premises(premises.iter().cloned())?;
+        Ok(ArgContext {
+            goal_tokens: tokenizer.tokenize_aligned(goal),
+            premises,
+            premise_names,
+            max_distinct_premises,
+            term_spans: false,
+        })
+    }
+    /// Finds a term in the goal (within the words the model sees) or
+    /// in the type of a premise. Terms are matched word by word, so
+    /// whitespace doesn't matter, and spans are in words rather than
+    /// tokens, so they can be decoded without a tokenizer.
+    fn find_term_span(&self, args: &DataloaderArgs, term: &str) -> Option<TacticArgument> {
+        let term_words = get_words(term);
+        if term_words.len() == 0 {
+            return None;
+        }
+        let find_in = |words: &[&str]| {
+            words
+                .windows(term_words.len())
+                .position(|window| window == term_words.as_slice())
+        };
+        let goal_words: Vec<&str> = self
+            .goal_tokens
+            .words
+            .iter()
+            .zip(self.goal_tokens.word_to_token.iter())
+            .take_while(|(_word, token_idx)| token_idx.map_or(true, |idx| idx < args.max_length))
+            .map(|(word, _token_idx)| word.text)
+            .collect();
+        if let Some(start) = find_in(&goal_words) {
+            return Some(TacticArgument::TermSpan(
+                TermSource::Goal,
+                start,
+                start + term_words.len(),
+            ));
+        }
+        self.premises.iter().enumerate().find_map(|(idx, premise)| {
+            find_in(&get_words(get_hyp_type(premise))).map(|start| {
+                TacticArgument::TermSpan(TermSource::Premise(idx), start, start + term_words.len())
+            })
+        })
+    }
+}
+
+fn argument_premise(argument: &TacticArgument) -> Option<usize> {
+    match argument {
+        TacticArgument::HypVar(idx) => Some(*idx),
+        TacticArgument::TermSpan(TermSource::Premise(idx), _, _) => Some(*idx),
+        _ => None,
+    }
+}
+
+/// Finds the goal tokens, premises and (if context.term_spans is set)
+/// terms that the arguments of a tactic refer to. Returns them along
+/// with the stem to predict, which is just the tactic stem when the
+/// argument string is nothing but the encoded arguments. Clause
+/// keywords and punctuation stay in the stem. If any other word can't
+/// be encoded, or the arguments need more than max_arity(args) slots
+/// or context.max_distinct_premises different premises, the tactic is
+/// encoded as its bare stem and a single Unrecognized argument.
+fn encode_arguments(
+    args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    tactic_stem: &str,
+    tactic_argstr: &str,
+    context: &ArgContext,
+) -> (String, Vec<TacticArgument>) {
+    let mut argstr = tactic_argstr.trim();
+    if argstr.ends_with('.') {
+        argstr = argstr[..argstr.len() - 1].trim_end();
+    }
+    // Tactics like rewrite only take hypotheses and lemmas, so an
+    // argument that also appears in the goal shouldn't be encoded
+    // as a goal token.
+    let goal_symbols: Vec<&str> = if signatures.takes_goal_args(tactic_stem) {
+        context
+            .goal_tokens
+            .token_to_word
+            .iter()
             .take(args.max_length)
-            .enumerate()
-            .find(|(_idx, symbol)| symbol_matches(*symbol, arg_token))
-        {
-            Some((idx, _symbol)) => {
-                return arg_to_index(args, TacticArgument::GoalToken(idx));
+            .map(|word_idx| context.goal_tokens.words[*word_idx].text)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    // Parenthesized terms which appear in the goal or a premise are
+    // encoded whole, and the words in them skipped.
+    let mut candidates: Vec<((usize, usize), Option<TacticArgument>)> = Vec::new();
+    if context.term_spans {
+        for (start, end) in top_level_paren_groups(argstr) {
+            let inner = &argstr[start + 1..end - 1];
+            if get_words(inner).len() > 1 {
+                if let Some(span) = context.find_term_span(args, inner) {
+                    candidates.push(((start + 1, end - 1), Some(span)));
+                }
             }
-            None => (),
         }
-        match indexed_premises(hyps.iter().map(|s| s.as_ref()))
-            .into_iter()
-            .find(|(_idx, hname)| *hname == arg_token)
+    }
+    for word in lex_code(argstr) {
+        if is_arg_word(word.kind)
+            && !candidates
+                .iter()
+                .any(|((start, end), _)| word.start >= *start && word.end <= *end)
+            && !CLAUSE_KEYWORDS.contains(&word.text)
         {
-            Some((idx, _hname)) => {
-                return arg_to_index(args, TacticArgument::HypVar(idx));
+            candidates.push(((word.start, word.end), None));
+        }
+    }
+    candidates.sort_by_key(|((start, _end), _span)| *start);
+
+    let unrecognized = (tactic_stem.to_string(), vec![TacticArgument::Unrecognized]);
+    let mut template = String::new();
+    let mut arguments = Vec::new();
+    let mut distinct_premises: Vec<usize> = Vec::new();
+    let mut last_end = 0;
+    for ((start, end), span) in candidates {
+        if arguments.len() == max_arity(args) {
+            return unrecognized;
+        }
+        let word = &argstr[start..end];
+        let argument = match span {
+            Some(span) => span,
+            None => match goal_symbols
+                .iter()
+                .position(|symbol| symbol_matches(symbol, word))
+            {
+                Some(idx) => TacticArgument::GoalToken(idx),
+                None => match context
+                    .premise_names
+                    .iter()
+                    .find(|(_idx, hname)| hname == word)
+                {
+                    Some((idx, _hname)) => TacticArgument::HypVar(*idx),
+                    None => return unrecognized,
+                },
+            },
+        };
+        if let Some(idx) = argument_premise(&argument) {
+            if !distinct_premises.contains(&idx) {
+                if distinct_premises.len() == context.max_distinct_premises {
+                    return unrecognized;
+                }
+                distinct_premises.push(idx);
             }
-            None => panic!(
-                "An unknown tactic made it past the context filter with args: {}\n\
-                            Hyps are {:?}\n\
-                            Goal is {}",
-                arg, hyps, goal
-            ),
         }
+        template.push_str(&argstr[last_end..start]);
+        template.push_str(ARG_HOLE);
+        last_end = end;
+        arguments.push(argument);
     }
+    template.push_str(&argstr[last_end..]);
+    if template.split_whitespace().all(|word| word == ARG_HOLE) {
+        (tactic_stem.to_string(), arguments)
+    } else {
+        (format!("{} {}", tactic_stem, template), arguments)
+    }
+}
+
+/// The byte ranges of the outermost parenthesized groups in a string,
+/// including the parens.
+fn top_level_paren_groups(s: &str) -> Vec<(usize, usize)> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut group_start = 0;
+    for (idx, c) in s.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    group_start = idx;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push((group_start, idx + 1));
+                }
+            }
+            _ => (),
+        }
+    }
+    groups
 }
 
-fn get_argument<'a>(
+/// Encodes the arguments of a training tactic, and picks the premises
+/// the model will see for it. When there are more than
+/// args.max_premises premises, the ones the arguments refer to are
+/// kept, at random positions among a random sample of the others.
+fn get_arguments<'a>(
     args: &DataloaderArgs,
+    signatures: &TacticSignatures,
+    tokenizer: &Tokenizer,
     scraped: &'a ScrapedTactic,
-) -> (TacticArgument, Vec<&'a String>) {
+) -> (String, Vec<TacticArgument>, Vec<&'a String>) {
     let all_hyps: Vec<&String> = scraped
         .context
         .focused_hyps()
@@ -646,77 +1015,272 @@ fn get_argument<'a>(
             }
         };
     }
-    let (_tactic_stem, tactic_argstr) = match split_tactic(&scraped.tactic) {
-        None => return (TacticArgument::Unrecognized, rand_bounded_hyps!()),
-        Some(x) => x,
+    let (tactic_stem, tactic_argstr) = split_tactic(&scraped.tactic)
+        .expect(&format!("Couldn't get the stem for {}", scraped.tactic));
+    let context = match ArgContext::new(
+        tokenizer,
+        scraped.context.focused_goal(),
+        all_hyps.iter().map(|s| s.as_ref()).collect(),
+        args.max_premises,
+    ) {
+        Ok(context) => context,
+        Err(_) => {
+            return (
+                tactic_stem,
+                vec![TacticArgument::Unrecognized],
+                rand_bounded_hyps!(),
+            )
+        }
     };
-    let argstr_tokens: Vec<&str> = tactic_argstr[..tactic_argstr.len() - 1]
-        .split_whitespace()
+    let (stem, arguments) =
+        encode_arguments(args, signatures, &tactic_stem, &tactic_argstr, &context);
+    if all_hyps.len() <= args.max_premises {
+        return (stem, arguments, rand_bounded_hyps!());
+    }
+    let mut referenced: Vec<usize> = Vec::new();
+    for idx in arguments.iter().filter_map(argument_premise) {
+        if !referenced.contains(&idx) {
+            referenced.push(idx);
+        }
+    }
+    let other_hyps: Vec<&String> = all_hyps
+        .iter()
+        .enumerate()
+        .filter(|(idx, _hyp)| !referenced.contains(idx))
+        .map(|(_idx, hyp)| *hyp)
         .collect();
-    if argstr_tokens.len() == 0 {
-        (TacticArgument::NoArg, rand_bounded_hyps!())
-    } else if argstr_tokens.len() > 1 {
-        assert!(
-            false,
-            "A multi argument tactic made it past the context filter!"
-        );
-        (TacticArgument::Unrecognized, rand_bounded_hyps!())
-    } else {
-        let goal_symbols = get_words(scraped.context.focused_goal());
-        let arg_token = argstr_tokens[0];
-        match goal_symbols
-            .into_iter()
-            .take(args.max_length)
-            .enumerate()
-            .find(|(_idx, symbol)| symbol_matches(*symbol, arg_token))
-        {
-            Some((idx, _symbol)) => {
-                return (TacticArgument::GoalToken(idx), rand_bounded_hyps!());
+    let mut selected_hyps: Vec<&String> = other_hyps
+        .choose_multiple(&mut thread_rng(), args.max_premises - referenced.len())
+        .map(|s| *s)
+        .collect();
+    for idx in referenced.iter() {
+        let new_hyp_idx = thread_rng().gen_range(0, selected_hyps.len() + 1);
+        selected_hyps.insert(new_hyp_idx, all_hyps[*idx]);
+    }
+    let new_idx = |idx: usize| {
+        selected_hyps
+            .iter()
+            .position(|hyp| std::ptr::eq(*hyp, all_hyps[idx]))
+            .unwrap()
+    };
+    let arguments = arguments
+        .into_iter()
+        .map(|argument| match argument {
+            TacticArgument::HypVar(idx) => TacticArgument::HypVar(new_idx(idx)),
+            TacticArgument::TermSpan(TermSource::Premise(idx), start, end) => {
+                TacticArgument::TermSpan(TermSource::Premise(new_idx(idx)), start, end)
             }
-            None => (),
-        };
-        match indexed_premises(all_hyps.iter().map(|s| s.as_ref()))
-            .into_iter()
-            .find(|(_idx, hname)| *hname == arg_token)
-        {
-            Some((idx, _hname)) => {
-                if all_hyps.len() > args.max_premises {
-                    let mut other_hyps = all_hyps.clone();
-                    other_hyps.remove(idx);
-                    let mut selected_hyps: Vec<&String> = other_hyps
-                        .choose_multiple(&mut thread_rng(), args.max_premises - 1)
-                        .map(|s| *s)
-                        .collect();
-                    // let mut selected_hyps: Vec<&String> =
-                    //     other_hyps.into_iter().take(args.max_premises - 1).collect();
-                    let new_hyp_idx = thread_rng().gen_range(0, args.max_premises);
-                    // let new_hyp_idx = args.max_premises - 1;
-                    selected_hyps.insert(new_hyp_idx, all_hyps[idx]);
-                    return (TacticArgument::HypVar(new_hyp_idx), selected_hyps);
-                } else {
-                    return (TacticArgument::HypVar(idx), all_hyps);
-                }
+            other => other,
+        })
+        .collect();
+    (stem, arguments, selected_hyps)
+}
+
+/// How many argument slots a tactic has. A max_arity of zero, the
+/// default, means one slot, as before multi-argument tactics could be
+/// encoded.
+fn max_arity(dargs: &DataloaderArgs) -> usize {
+    dargs.max_arity.max(1)
+}
+
+fn args_to_indices(dargs: &DataloaderArgs, arguments: Vec<TacticArgument>) -> Vec<i64> {
+    let mut indices: Vec<i64> = arguments
+        .into_iter()
+        .map(|arg| arg_to_index(dargs, arg))
+        .collect();
+    indices.resize(max_arity(dargs), arg_to_index(dargs, TacticArgument::NoArg));
+    indices
+}
+
+fn args_to_spans(
+    dargs: &DataloaderArgs,
+    arguments: &[TacticArgument],
+) -> Vec<Option<PickleableTermSpan>> {
+    let mut spans: Vec<Option<PickleableTermSpan>> = arguments
+        .iter()
+        .map(|arg| match arg {
+            TacticArgument::TermSpan(TermSource::Goal, start, end) => Some((None, *start, *end)),
+            TacticArgument::TermSpan(TermSource::Premise(idx), start, end) => {
+                Some((Some(*idx), *start, *end))
             }
-            None => (),
-        };
-        assert!(
-            false,
-            "An unknown tactic made it past the context filter: {}, arg {}, arg_token {}\n\
-             Goal is {}",
-            scraped.tactic,
-            argstr_tokens[0],
-            arg_token,
-            scraped.context.focused_goal()
-        );
-        (TacticArgument::Unrecognized, rand_bounded_hyps!())
-    }
+            _ => None,
+        })
+        .collect();
+    spans.resize(max_arity(dargs), None);
+    spans
 }
+
 fn arg_to_index(dargs: &DataloaderArgs, arg: TacticArgument) -> i64 {
     match arg {
         // For compatibility with the python version, we'll treat these as the same for now.
         TacticArgument::Unrecognized => 0,
         TacticArgument::NoArg => 0,
+        // Spans don't have an index; args_to_spans encodes them.
+        TacticArgument::TermSpan(..) => 0,
         TacticArgument::GoalToken(tidx) => (tidx + 1) as i64,
         TacticArgument::HypVar(hidx) => (hidx + dargs.max_length + 1) as i64,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(
+        args: &DataloaderArgs,
+        tokenizer: &Tokenizer,
+        goal: &str,
+        context: &ArgContext,
+        tactic: &str,
+    ) -> (String, String) {
+        let (tactic_stem, argstr) = split_tactic(tactic).unwrap();
+        let (stem, arguments) = encode_arguments(
+            args,
+            &TacticSignatures::default(),
+            &tactic_stem,
+            &argstr,
+            context,
+        );
+        let spans = args_to_spans(args, &arguments);
+        let indices = args_to_indices(args, arguments);
+        assert_eq!(indices.len(), args.max_arity);
+        let premises: Vec<String> = context.premises.iter().map(|s| s.to_string()).collect();
+        let decoded: Vec<String> = indices
+            .into_iter()
+            .zip(spans)
+            .map(|(idx, span)| match span {
+                Some(span) => decode_fpa_term_span(&premises, goal, span),
+                None => decode_fpa_arg(args, tokenizer, &premises, goal, idx).unwrap(),
+            })
+            .collect();
+        let result = fill_arg_holes(&stem, &decoded);
+        (stem, result)
+    }
+
+    #[test]
+    fn test_multiarg_round_trip() {
+        let mut args: DataloaderArgs = Default::default();
+        args.max_length = 30;
+        args.max_arity = 3;
+        let tokenizer = Tokenizer::from_keywords(true, 2, vec![]);
+        let goal = "P x y";
+        let context =
+            ArgContext::new(&tokenizer, goal, vec!["H1 : a = b", "H2 : b = c"], 2).unwrap();
+        for (tactic, expected_stem) in &[
+            ("rewrite H1 H2.", "rewrite"),
+            ("apply H1 with x.", "apply $ with $"),
+            ("destruct H2 as [x y].", "destruct $ as [$ $]"),
+            ("specialize (H1 x y).", "specialize ($ $ $)"),
+            ("intros.", "intros"),
+        ] {
+            let (stem, decoded) = round_trip(&args, &tokenizer, goal, &context, tactic);
+            assert_eq!(&stem, expected_stem);
+            assert_eq!(&decoded, tactic);
+        }
+        // Fresh names, too many arguments, and too many different
+        // premises all leave the tactic unrecognized, rather than
+        // putting argument text in the stem.
+        let narrow_context =
+            ArgContext::new(&tokenizer, goal, vec!["H1 : a = b", "H2 : b = c"], 1).unwrap();
+        for (context, tactic, expected_stem) in &[
+            (&context, "destruct H2 as [a b].", "destruct"),
+            (&context, "rewrite H1 H2 H1 H2.", "rewrite"),
+            (&narrow_context, "rewrite H1 H2.", "rewrite"),
+        ] {
+            let (stem, decoded) = round_trip(&args, &tokenizer, goal, context, tactic);
+            assert_eq!(&stem, expected_stem);
+            assert_eq!(decoded, format!("{}.", expected_stem));
+        }
+    }
+
+    #[test]
+    fn test_term_spans() {
+        let mut args: DataloaderArgs = Default::default();
+        args.max_length = 30;
+        args.max_arity = 3;
+        let tokenizer = Tokenizer::from_keywords(true, 2, vec![]);
+        let goal = "P (S  n) y";
+        let mut context =
+            ArgContext::new(&tokenizer, goal, vec!["H : x = y + 1", "n : nat"], 2).unwrap();
+        context.term_spans = true;
+        assert_eq!(
+            round_trip(&args, &tokenizer, goal, &context, "exists (S n)."),
+            ("exists ($)".to_string(), "exists (S n).".to_string())
+        );
+        assert_eq!(
+            round_trip(&args, &tokenizer, goal, &context, "assert (x =y+1)."),
+            ("assert ($)".to_string(), "assert (x = y + 1).".to_string())
+        );
+        assert_eq!(
+            round_trip(&args, &tokenizer, goal, &context, "replace (S n) with (n + y)."),
+            ("replace ($) with ($ + $)".to_string(), "replace (S n) with (n + y).".to_string())
+        );
+    }
+
+    /// Some points from the scrapes of propcalc, int-map, GeoCoq and
+    /// area-method.
+    const SCRAPE_EXCERPT: &str = r#"{"prev_tactics": [], "prev_hyps": ["a : list Literal", "ll1, ll2 : list (list Literal)"], "prev_goal": "Nc (cons (CNFtoPropF (cons a (app ll1 ll2))) nil) (CNFtoPropF (cons a ll1))", "relevant_lemmas": [], "tactic": "unfold CNFtoPropF."}
+{"prev_tactics": [], "prev_hyps": ["IHx2 : forall y : PropF, sumbool (eq x2 y) (not (eq x2 y))", "x1,x2 : PropF"], "prev_goal": "forall y : PropF, sumbool (eq (Disj x1 x2) y) (not (eq (Disj x1 x2) y))", "relevant_lemmas": [], "tactic": "destruct y."}
+{"prev_tactics": [], "prev_hyps": ["H0 : sig (fun y : A => eq (MapGet A m a) (Some y))", "a : ad", "m : Map A", "A : Type"], "prev_goal": "eq (MapGet A m a) None", "relevant_lemmas": [], "tactic": "elim H0."}
+{"prev_tactics": [], "prev_hyps": ["H0 : Pos.eq (sndpp t1) (sndpp t2)", "t1,t2 : True"], "prev_goal": "Logic.eq (Pos.eqb (sndpp t1) (sndpp t2)) true", "relevant_lemmas": [], "tactic": "unfold Pos.eqb."}
+{"prev_tactics": [], "prev_hyps": [], "prev_goal": "forall (A B C : Point) (_ : Cong A B C C), eq A B", "relevant_lemmas": [], "tactic": "unfold Cong."}
+{"prev_tactics": [], "prev_hyps": ["H : eq (Py4 A C B D) F0", "A,B,C,D : Point"], "prev_goal": "eq (Fopp (Py4 A C B D)) F0", "relevant_lemmas": [], "tactic": "rewrite H."}"#;
+
+    #[test]
+    fn test_goal_alignment() {
+        let path = std::env::temp_dir().join(format!("alignment-{}.scrape", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, SCRAPE_EXCERPT).unwrap();
+        let data: Vec<ScrapedTactic> =
+            scraped_tactics_iter(scraped_from_file(path, BadLinePolicy::Fail).unwrap())
+                .collect::<Result<_, _>>()
+                .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(data.len(), 6);
+
+        let mut args: DataloaderArgs = Default::default();
+        args.max_length = 8;
+        args.max_premises = 4;
+        let signatures = TacticSignatures::default();
+        // Without unknown tokens, most words are dropped, so token
+        // positions and word positions disagree.
+        let keywords = ["eq", "(", ")", "Cong", "Pos.eqb", "sumbool", "y"];
+        for tokenizer in &[
+            Tokenizer::from_keywords(true, 2, vec![]),
+            Tokenizer::from_keywords(false, 2, keywords.iter().map(|s| s.to_string()).collect()),
+        ] {
+            for scraped in data.iter() {
+                let goal = scraped.context.focused_goal();
+                let (tokens, mask) = goal_tensors(tokenizer, goal, args.max_length);
+                let (stem, arguments, selected) =
+                    get_arguments(&args, &signatures, tokenizer, scraped);
+                let premises: Vec<String> = selected.into_iter().cloned().collect();
+                // Words past the goal tokens the model sees can't be
+                // encoded.
+                if let [TacticArgument::Unrecognized] = arguments.as_slice() {
+                    assert_eq!(Some(stem), split_tactic(&scraped.tactic).map(|(stem, _)| stem));
+                    continue;
+                }
+                let mut decoded = Vec::new();
+                for argument in arguments {
+                    if let TacticArgument::GoalToken(idx) = argument {
+                        assert!(mask[idx + 1], "{} in {}", idx, goal);
+                        let word = decode_fpa_arg(
+                            &args,
+                            tokenizer,
+                            &premises,
+                            goal,
+                            arg_to_index(&args, argument.clone()),
+                        )
+                        .unwrap();
+                        assert_eq!(tokenizer.tokenize(&word), vec![tokens[idx]]);
+                    }
+                    let arg_idx = arg_to_index(&args, argument);
+                    decoded
+                        .push(decode_fpa_arg(&args, tokenizer, &premises, goal, arg_idx).unwrap());
+                }
+                assert_eq!(fill_arg_holes(&stem, &decoded), scraped.tactic);
+            }
+        }
+    }
+}
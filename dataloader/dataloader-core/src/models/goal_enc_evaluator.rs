@@ -1,12 +1,12 @@
-use bincode::{deserialize, serialize};
+use bincode::serialize;
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 use pyo3::ToPyObject;
 use rayon::prelude::*;
-use std::fs::File;
 
-use crate::context_filter::filter_data_by_key;
+use crate::context_filter::{filter_data_by_key, parse_tactic_filter};
 use crate::models::evaluator_common::*;
+use crate::dataset_manifest::scraped_from_path;
 use crate::scraped_data::*;
 use crate::tokenizer::{normalize_sentence_length, Tokenizer};
 
@@ -29,7 +29,8 @@ impl GoalEncMetadata {
         .to_object(py))
     }
     fn __setstate__(&mut self, py: Python, state: PyObject) -> PyResult<()> {
-        self.tokenizer = Some(deserialize(state.extract::<&PyBytes>(py)?.as_bytes()).unwrap());
+        let bytes = state.extract::<&PyBytes>(py)?.as_bytes();
+        self.tokenizer = Some(Tokenizer::from_bytes(bytes).unwrap());
         Ok(())
     }
 }
@@ -38,13 +39,16 @@ pub fn goals_to_total_distances_tensors(
     args: DataloaderArgs,
     filename: String,
     metadata: Option<&GoalEncMetadata>,
-) -> Result<(GoalEncMetadata, LongTensor2D, FloatTensor1D), String> {
-    let raw_data =
-        scraped_from_file(File::open(filename).map_err(|_err| "Failed to open file")?).collect();
-    let distanced = tactic_distances(raw_data);
-    let filtered_data = filter_data_by_key(&args, &args.context_filter, distanced, |distanced| {
+) -> PyResult<(GoalEncMetadata, LongTensor2D, FloatTensor1D)> {
+    let filter = parse_tactic_filter(&args.context_filter)?;
+    let signatures = args.load_tactic_signatures()?;
+    let bad_lines = BadLineCount::default();
+    let raw_data = scraped_from_path(&args, &filename, &bad_lines)?.collect::<Result<_, _>>()?;
+    bad_lines.warn_if_any(&filename)?;
+    let distanced = tactic_distances(raw_data)?;
+    let filtered_data = filter_data_by_key(&args, &signatures, &filter, distanced, |distanced| {
         &(*distanced).0
-    });
+    })?;
     let (tactics, distances): (Vec<ScrapedTactic>, Vec<usize>) = filtered_data.into_iter().unzip();
     let tokenizer = match metadata {
         Some(meta) => meta.tokenizer.as_ref().expect("No tokenizer").clone(),
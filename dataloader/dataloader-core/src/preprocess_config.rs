@@ -0,0 +1,136 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// One step of tactic preprocessing. Comments are always stripped
+/// first; the steps then run in the order the config lists them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreprocessStep {
+    /// Keep only the part of a tactic before its first ";"
+    TruncateSemicolons,
+    /// Replace a tactic that chains with ";" by the first of the
+    /// single-goal steps linearize_tactic expands it into. Where the
+    /// data has the context after each tactic, chains that can't be
+    /// linearized are left out instead of being truncated.
+    Linearize,
+    /// Replace tactics whose stem is in the substitution table
+    Substitute,
+    /// Turn numeric induction/destruct arguments into the names of
+    /// the binders they refer to
+    NumericArgs,
+}
+
+/// How tactics are normalized before training. The defaults match
+/// what the preprocessor has always done, so leaving
+/// DataloaderArgs.preprocess_config unset changes nothing. That's why
+/// Linearize isn't a default step: it drops the chains it can't
+/// linearize, which TruncateSemicolons keeps, so adding it would
+/// change the data existing models were trained on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    pub steps: Vec<PreprocessStep>,
+    /// Maps a tactic stem to the full tactic that replaces it
+    pub substitutions: HashMap<String, String>,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        let mut substitutions = HashMap::new();
+        substitutions.insert("auto".to_string(), "eauto.".to_string());
+        substitutions.insert("intros until".to_string(), "intros.".to_string());
+        substitutions.insert("intro".to_string(), "intros.".to_string());
+        substitutions.insert("constructor".to_string(), "econstructor.".to_string());
+        PreprocessConfig {
+            steps: vec![
+                PreprocessStep::TruncateSemicolons,
+                PreprocessStep::Substitute,
+                PreprocessStep::NumericArgs,
+            ],
+            substitutions,
+        }
+    }
+}
+
+fn invalid_config(path: &str, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid preprocessing config {}: {}", path, err),
+    )
+}
+
+impl PreprocessConfig {
+    /// Whether a tactic chained with ";" gets cut down to its first
+    /// step.
+    pub fn splits_chains(&self) -> bool {
+        self.steps.iter().any(|step| {
+            *step == PreprocessStep::TruncateSemicolons || *step == PreprocessStep::Linearize
+        })
+    }
+    /// Loads a config from a .toml file, or from json otherwise.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        if path.ends_with(".toml") {
+            toml::from_str(&contents).map_err(|err| invalid_config(path, err))
+        } else {
+            serde_json::from_str(&contents).map_err(|err| invalid_config(path, err))
+        }
+    }
+    /// The config as a string, for recording in model metadata.
+    pub fn to_pickleable(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+    pub fn from_pickleable(pick: &str) -> io::Result<Self> {
+        serde_json::from_str(pick).map_err(|err| invalid_config("in metadata", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_preprocess_config() {
+        let path = std::env::temp_dir().join(format!("preprocess-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "steps = [\"substitute\"]\n\n[substitutions]\n\"intros until\" = \"intros.\"\n",
+        )
+        .unwrap();
+        let config = PreprocessConfig::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.steps, vec![PreprocessStep::Substitute]);
+        assert_eq!(config.substitutions.len(), 1);
+        assert_eq!(
+            PreprocessConfig::from_pickleable(&config.to_pickleable()).unwrap(),
+            config
+        );
+        assert_eq!(
+            serde_json::from_str::<PreprocessConfig>("{}").unwrap(),
+            PreprocessConfig::default()
+        );
+    }
+}
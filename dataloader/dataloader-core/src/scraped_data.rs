@@ -19,19 +19,31 @@
 //
 /* *********************************************************************** */
 
-use crate::tokenizer::get_symbols;
+use crate::binders::numbered_premise;
+use crate::command_kind::{command_kind, CommandKind};
+use crate::coq_lexer::{lex, LexemeKind};
+use crate::compression::{open_maybe_compressed, MaybeCompressedWriter};
+use crate::context_delta::{context_delta, ContextDelta};
+use crate::hypothesis::{parse_hypothesis, HypothesisError};
+use crate::linearize::{goals_created, linearize_tactic, LinearizeError};
+use crate::preprocess_config::{PreprocessConfig, PreprocessStep};
+use crate::tactic_signature::TacticSignatures;
+use crate::tokenizer::get_words;
 use core::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+use pyo3::exceptions;
 use pyo3::prelude::*;
 use pyo3::types::PyAny;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Result, Write};
+use std::io::{self, BufRead, Write};
 use std::iter;
+use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 use crate::paren_util::*;
-use regex::Regex;
 
 pub type FloatUnpaddedTensor3D = Vec<Vec<Vec<f64>>>;
 pub type LongUnpaddedTensor3D = Vec<Vec<Vec<i64>>>;
@@ -47,7 +59,7 @@ pub type LongTensor1D = Vec<i64>;
 pub type FloatTensor1D = Vec<f64>;
 
 #[pyclass]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ScrapedTactic {
     #[pyo3(get, set)]
     pub relevant_lemmas: Vec<String>,
@@ -57,6 +69,8 @@ pub struct ScrapedTactic {
     pub context: ProofContext,
     #[pyo3(get, set)]
     pub tactic: String,
+    #[pyo3(get, set)]
+    pub provenance: Option<Provenance>,
 }
 
 impl ScrapedTactic {
@@ -66,9 +80,34 @@ impl ScrapedTactic {
             prev_tactics: self.prev_tactics.clone(),
             context: self.context.clone(),
             tactic: tactic,
+            provenance: self.provenance.clone(),
         }
     }
 }
+
+#[pymethods]
+impl ScrapedTactic {
+    /// What the tactic does to the structure of the proof, like
+    /// opening a subgoal or ending the proof.
+    #[getter]
+    pub fn command_kind(&self) -> CommandKind {
+        command_kind(&self.tactic)
+    }
+}
+
+/// Where a scraped tactic came from, so that bad samples can be
+/// traced back to their source. Line numbers start at one, and proofs
+/// are numbered from zero within each file.
+#[pyclass]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Provenance {
+    #[pyo3(get, set)]
+    pub filename: String,
+    #[pyo3(get, set)]
+    pub line_number: usize,
+    #[pyo3(get, set)]
+    pub proof_index: usize,
+}
 #[pyclass]
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TacticContext {
@@ -97,7 +136,7 @@ impl TacticContext {
 }
 
 #[pyclass]
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
 pub struct Obligation {
     #[pyo3(get, set)]
     pub hypotheses: Vec<String>,
@@ -115,7 +154,7 @@ impl Obligation {
 
 
 #[pyclass]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ProofContext {
     #[pyo3(get, set)]
     pub fg_goals: Vec<Obligation>,
@@ -169,6 +208,27 @@ pub struct ScrapedTransition {
     pub after: ProofContext,
     #[pyo3(get, set)]
     pub tactic: String,
+    #[pyo3(get, set)]
+    pub provenance: Option<Provenance>,
+    /// The original tactic as a sequence of single-goal steps, or None
+    /// if it chains tactics in a way that couldn't be linearized. When
+    /// this has more than one step, the after context is the context
+    /// after all of them.
+    #[pyo3(get, set)]
+    pub linearized: Option<Vec<String>>,
+    /// Whether preprocessing cut tactic down to the first step of a
+    /// chain that ran more steps. The after context still comes after
+    /// the whole chain, so it isn't what tactic alone would leave.
+    #[pyo3(get, set)]
+    pub partial: bool,
+}
+
+#[pymethods]
+impl ScrapedTransition {
+    /// What the tactic changed between the before and after contexts.
+    pub fn delta(&self) -> ContextDelta {
+        context_delta(&self.before, &self.after)
+    }
 }
 
 impl ScrapedTransition {
@@ -178,349 +238,743 @@ impl ScrapedTransition {
             prev_tactics: self.prev_tactics.clone(),
             context: self.before.clone(),
             tactic: self.tactic.clone(),
+            provenance: self.provenance.clone(),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct VernacCommand {
     pub command: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ScrapedData {
     Vernac(VernacCommand),
     Tactic(ScrapedTactic),
 }
 
-pub struct AdjacentPairs<I, T>
-where
-    I: Iterator<Item = T>,
-    T: Clone,
-{
-    pk: iter::Peekable<I>,
-}
-impl<I, T> AdjacentPairs<I, T>
-where
-    I: Iterator<Item = T>,
-    T: Clone,
-{
-    fn new(it: I) -> Self {
-        AdjacentPairs { pk: it.peekable() }
-    }
-}
-impl<I: Iterator<Item = T>, T: Clone> Iterator for AdjacentPairs<I, T> {
-    type Item = (I::Item, Option<I::Item>);
-    fn next(&mut self) -> Option<(I::Item, Option<I::Item>)> {
-        match self.pk.next() {
-            Some(v) => Some((v, self.pk.peek().cloned())),
-            None => None,
-        }
-    }
+/// A single proof from a scrape file.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct ScrapedProof {
+    /// The vernac command that opened the proof, usually the lemma
+    /// statement, if there was one before it
+    #[pyo3(get, set)]
+    pub statement: Option<String>,
+    /// Every tactic run in the proof, in order. This includes the
+    /// closing command when it was scraped as a tactic.
+    #[pyo3(get, set)]
+    pub tactics: Vec<ScrapedTactic>,
+    /// The command that ended the proof, like "Qed.", if the proof
+    /// was finished
+    #[pyo3(get, set)]
+    pub closing_command: Option<String>,
 }
 
-pub fn scraped_transition_iter(
-    scraped: impl iter::Iterator<Item = ScrapedData>,
-) -> impl iter::Iterator<Item = ScrapedTransition> {
-    AdjacentPairs::new(scraped).flat_map(|(first, next)| match first {
-        ScrapedData::Vernac(_) => None,
-        ScrapedData::Tactic(t) => {
-            let context_after = match next {
-                Some(ScrapedData::Tactic(t_after)) => t_after.context.clone(),
-                _ => ProofContext::empty(),
-            };
-            let p = preprocess_datum(t);
-            Some(ScrapedTransition {
-                relevant_lemmas: p.relevant_lemmas.clone(),
-                prev_tactics: p.prev_tactics.clone(),
-                before: p.context.clone(),
-                after: context_after,
-                tactic: p.tactic.clone(),
+impl ScrapedProof {
+    /// The context after each tactic in the proof. The last tactic
+    /// leaves an empty context.
+    fn contexts_after(&self) -> impl Iterator<Item = ProofContext> + '_ {
+        self.tactics
+            .iter()
+            .skip(1)
+            .map(|t_after| t_after.context.clone())
+            .chain(iter::once(ProofContext::empty()))
+    }
+    /// The tactics in the proof, preprocessed. Chains that the
+    /// Linearize step can't linearize are left out.
+    pub fn preprocessed_tactics(&self, config: &PreprocessConfig) -> Vec<ScrapedTactic> {
+        self.tactics
+            .iter()
+            .zip(self.contexts_after())
+            .filter_map(|(t, context_after)| {
+                preprocess_datum_with_after(config, t.clone(), &context_after)
             })
+            .collect()
+    }
+    /// The transitions made by each tactic in the proof, with
+    /// preprocessed tactics, leaving out the ones preprocessed_tactics
+    /// leaves out.
+    pub fn transitions(&self, config: &PreprocessConfig) -> Vec<ScrapedTransition> {
+        self.tactics
+            .iter()
+            .zip(self.contexts_after())
+            .filter_map(|(t, context_after)| {
+                let linearized =
+                    linearize_tactic(&t.tactic, goals_created(&t.context, &context_after)).ok();
+                let p = preprocess_datum_with_after(config, t.clone(), &context_after)?;
+                let partial = config.splits_chains()
+                    && linearized.as_ref().map_or(true, |steps| steps.len() > 1);
+                Some(ScrapedTransition {
+                    relevant_lemmas: p.relevant_lemmas,
+                    prev_tactics: p.prev_tactics,
+                    before: p.context,
+                    after: context_after,
+                    tactic: p.tactic,
+                    provenance: p.provenance,
+                    linearized,
+                    partial,
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn is_proof_terminator(command: &str) -> bool {
+    match command_kind(command) {
+        CommandKind::ProofEnd(_) => true,
+        _ => false,
+    }
+}
+
+/// Groups scraped data into proofs. A proof is a maximal run of
+/// tactics; the last vernac before it is taken as its statement, and
+/// a proof terminator at the end of the run (or in the vernac right
+/// after it) as its closing command.
+pub fn scraped_proofs_iter<E>(
+    scraped: impl iter::Iterator<Item = Result<ScrapedData, E>>,
+) -> impl iter::Iterator<Item = Result<ScrapedProof, E>> {
+    let mut scraped = scraped.peekable();
+    let mut last_vernac: Option<String> = None;
+    iter::from_fn(move || {
+        let first_tactic = loop {
+            match scraped.next()? {
+                Ok(ScrapedData::Vernac(cmd)) => last_vernac = Some(cmd.command),
+                Ok(ScrapedData::Tactic(t)) => break t,
+                Err(err) => return Some(Err(err)),
+            }
+        };
+        let mut tactics = vec![first_tactic];
+        while let Some(Ok(ScrapedData::Tactic(_))) = scraped.peek() {
+            if let Some(Ok(ScrapedData::Tactic(t))) = scraped.next() {
+                tactics.push(t);
+            }
         }
+        let last_tactic = &tactics[tactics.len() - 1].tactic;
+        let closing_command = if is_proof_terminator(last_tactic) {
+            Some(last_tactic.trim().to_string())
+        } else {
+            match scraped.peek() {
+                Some(Ok(ScrapedData::Vernac(cmd))) if is_proof_terminator(&cmd.command) => {
+                    let closing = cmd.command.trim().to_string();
+                    scraped.next();
+                    Some(closing)
+                }
+                _ => None,
+            }
+        };
+        Some(Ok(ScrapedProof {
+            statement: last_vernac.take(),
+            tactics,
+            closing_command,
+        }))
     })
 }
 
-pub fn scraped_from_file(file: File) -> impl iter::Iterator<Item = ScrapedData> {
-    BufReader::new(file).lines().map(|line: Result<String>| {
-        let actual_line = line.expect("Couldn't read line");
-        if actual_line.starts_with("\"") {
-            ScrapedData::Vernac(VernacCommand {
-                command: serde_json::from_str(&actual_line).expect("Couldn't parse string"),
-            })
-        } else {
-            ScrapedData::Tactic(serde_json::from_str(&actual_line).expect("Couldn't parse line"))
-        }
+pub fn scraped_transition_iter<'a>(
+    config: &'a PreprocessConfig,
+    scraped: impl iter::Iterator<Item = Result<ScrapedData, ScrapeError>> + 'a,
+) -> impl iter::Iterator<Item = Result<ScrapedTransition, ScrapeError>> + 'a {
+    scraped_proofs_iter(scraped).flat_map(move |proof| match proof {
+        Ok(proof) => proof.transitions(config).into_iter().map(Ok).collect(),
+        Err(err) => vec![Err(err)],
     })
 }
 
-pub fn scraped_to_file(mut file: File, scraped: impl iter::Iterator<Item = ScrapedData>) {
-    for point in scraped {
-        match point {
-            ScrapedData::Vernac(VernacCommand { command: cmd }) => {
-                writeln!(&mut file, "\"{}\"", cmd).unwrap()
-            }
-            ScrapedData::Tactic(tac) => {
-                serde_json::to_writer(
-                    &mut file,
-                    &serde_json::json!({
-                    "prev_tactics": tac.prev_tactics,
-                    "prev_hyps": tac.context.focused_hyps(),
-                    "prev_goal": tac.context.focused_goal(),
-                    "relevant_lemmas": tac.relevant_lemmas,
-                    "tactic": tac.tactic}),
-                )
-                .unwrap();
-                writeln!(&mut file, "").unwrap();
-            }
+/// Reads the tactics out of scraped data and preprocesses them, using
+/// the context after each tactic (see ScrapedProof::preprocessed_tactics).
+pub fn preprocessed_tactics_iter<'a>(
+    config: &'a PreprocessConfig,
+    scraped: impl iter::Iterator<Item = Result<ScrapedData, ScrapeError>> + 'a,
+) -> impl iter::Iterator<Item = Result<ScrapedTactic, ScrapeError>> + 'a {
+    scraped_proofs_iter(scraped).flat_map(move |proof| match proof {
+        Ok(proof) => proof.preprocessed_tactics(config).into_iter().map(Ok).collect(),
+        Err(err) => vec![Err(err)],
+    })
+}
+
+/// What to do when a line of a scrape file can't be read.
+#[derive(Clone, Debug)]
+pub enum BadLinePolicy {
+    /// Stop reading and report the first bad line as an error
+    Fail,
+    /// Skip bad lines, counting them in the reader's num_bad_lines
+    Skip,
+    /// Like Skip, but also write every bad line, along with where it
+    /// came from, to the given file
+    Collect(String),
+}
+
+#[derive(Debug)]
+pub enum ScrapeErrorKind {
+    Io(io::Error),
+    Utf8(FromUtf8Error),
+    Json(serde_json::Error),
+}
+
+/// An error reading a scrape file. Line numbers start at one, and
+/// are zero for errors that don't belong to any line (like failing
+/// to open the file).
+#[derive(Debug)]
+pub struct ScrapeError {
+    pub filename: String,
+    pub line_number: usize,
+    pub byte_offset: usize,
+    pub kind: ScrapeErrorKind,
+}
+
+impl fmt::Display for ScrapeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScrapeErrorKind::Io(err) => write!(f, "{}", err),
+            ScrapeErrorKind::Utf8(err) => write!(f, "invalid utf-8: {}", err),
+            ScrapeErrorKind::Json(err) => write!(f, "couldn't parse json: {}", err),
         }
     }
 }
 
-pub fn kill_comments(source: &str) -> String {
-    let mut result = String::new();
-    let mut depth = 0;
-    let mut cur_pos = 0;
-    macro_rules! lookup {
-        ($pat:expr) => {
-            source[cur_pos..]
-                .find($pat)
-                .map(|pos| pos + cur_pos)
-                .unwrap_or(source.len())
-        };
-    };
-    while cur_pos < source.len() {
-        let next_open = lookup!("(*");
-        let next_close = lookup!("*)");
-        if depth == 0 {
-            // assert!(
-            //     next_open <= next_close,
-            //     "Unbalanced comment delimiters! Too many closes"
-            // );
-            if next_open <= next_close {
-                result.push_str(&source[cur_pos..next_open]);
-                cur_pos = next_open + 2;
-                depth += 1;
-            } else {
-                cur_pos = next_close + 2;
-            }
-        } else if next_open < next_close {
-            depth += 1;
-            cur_pos = next_open + 2;
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line_number == 0 {
+            write!(f, "{}: {}", self.filename, self.kind)
         } else {
-            assert!(
-                next_close < next_open,
-                "Unbalanced comment delimiters! Not enough closes"
-            );
-            assert!(depth > 0);
-            depth -= 1;
-            cur_pos = next_close + 2;
+            write!(
+                f,
+                "{}:{} (byte {}): {}",
+                self.filename, self.line_number, self.byte_offset, self.kind
+            )
         }
     }
-    result
 }
 
-pub fn split_tactic(full_tactic: &str) -> Option<(String, String)> {
-    let no_comments_tac = kill_comments(full_tactic);
-    let prepped_tac = no_comments_tac.trim();
-    lazy_static! {
-        static ref GOAL_SELECTOR: Regex = Regex::new(r"^\s*[-+*{}]+\s*$").unwrap();
+impl std::error::Error for ScrapeError {}
+
+impl From<ScrapeError> for PyErr {
+    fn from(err: ScrapeError) -> PyErr {
+        exceptions::PyValueError::new_err((
+            err.to_string(),
+            err.filename,
+            err.line_number,
+            err.byte_offset,
+        ))
     }
-    if GOAL_SELECTOR.is_match(prepped_tac) || prepped_tac.contains(';') {
-        return None;
+}
+
+/// A count of skipped lines that can be shared between readers,
+/// including ones on other threads, so that an entry point can report
+/// the total once it's done reading.
+#[derive(Clone, Default)]
+pub struct BadLineCount(Arc<AtomicUsize>);
+
+impl BadLineCount {
+    pub fn get(&self) -> usize {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+    fn add_one(&self) {
+        self.0.fetch_add(1, AtomicOrdering::Relaxed);
     }
-    for prefix in &["try", "now", "repeat", "decide"] {
-        if prepped_tac.starts_with(prefix) {
-            return split_tactic(&prepped_tac[prefix.len()..].trim()).map(
-                |(rest_stem, rest_rest)| {
-                    let mut new_stem = prefix.to_string();
-                    new_stem.push_str(" ");
-                    new_stem.push_str(&rest_stem);
-                    (new_stem, rest_rest)
-                },
-            );
+    /// Warns in python if any lines of filename were skipped. Like
+    /// warn_py, only call this from the thread holding the GIL.
+    pub fn warn_if_any(&self, filename: &str) -> PyResult<()> {
+        match self.get() {
+            0 => Ok(()),
+            num => warn_py(&format!("Skipped {} bad lines in {}", num, filename)),
         }
     }
-    for special_stem in &["rewrite <-", "rewrite !", "intros until", "simpl in"] {
-        if prepped_tac.starts_with(special_stem) {
-            return Some((
-                special_stem.to_string(),
-                prepped_tac[special_stem.len()..].to_string(),
-            ));
+}
+
+/// An iterator over the commands in a scrape file, which handles
+/// lines that can't be parsed according to a BadLinePolicy. For
+/// compressed files, byte offsets are into the decompressed text.
+pub struct ScrapeReader {
+    filename: String,
+    reader: Box<dyn BufRead>,
+    policy: BadLinePolicy,
+    bad_lines_file: Option<File>,
+    line_number: usize,
+    byte_offset: usize,
+    num_bad_lines: usize,
+    shared_count: BadLineCount,
+    num_proofs: usize,
+    in_proof: bool,
+    failed: bool,
+}
+
+impl ScrapeReader {
+    /// How many lines have been skipped so far, under the Skip and
+    /// Collect policies
+    pub fn num_bad_lines(&self) -> usize {
+        self.num_bad_lines
+    }
+    /// Also counts skipped lines in count, which may be shared with
+    /// other readers.
+    pub fn counting_into(mut self, count: &BadLineCount) -> Self {
+        self.shared_count = count.clone();
+        self
+    }
+    /// Tags tactics with where they came from. Proofs are delimited
+    /// the same way as in tactic_distances: a proof starts at the
+    /// first tactic after a vernac command.
+    fn with_provenance(&mut self, datum: ScrapedData) -> ScrapedData {
+        match datum {
+            ScrapedData::Vernac(cmd) => {
+                self.in_proof = false;
+                ScrapedData::Vernac(cmd)
+            }
+            ScrapedData::Tactic(mut tac) => {
+                if !self.in_proof {
+                    self.in_proof = true;
+                    self.num_proofs += 1;
+                }
+                tac.provenance = Some(Provenance {
+                    filename: self.filename.clone(),
+                    line_number: self.line_number,
+                    proof_index: self.num_proofs - 1,
+                });
+                ScrapedData::Tactic(tac)
+            }
         }
     }
-    prepped_tac
-        .find(|c| !(char::is_alphabetic(c) || c == '\'' || c == '_' || c == '('))
-        .map(|idx| {
-            (
-                prepped_tac[..idx].to_string(),
-                prepped_tac[idx..].to_string(),
-            )
-        })
+    fn line_error(&self, offset: usize, kind: ScrapeErrorKind) -> ScrapeError {
+        ScrapeError {
+            filename: self.filename.clone(),
+            line_number: self.line_number,
+            byte_offset: offset,
+            kind,
+        }
+    }
+    fn record_bad_line(&mut self, err: &ScrapeError, line: &[u8]) -> io::Result<()> {
+        self.num_bad_lines += 1;
+        self.shared_count.add_one();
+        if let Some(file) = &mut self.bad_lines_file {
+            serde_json::to_writer(
+                &mut *file,
+                &serde_json::json!({
+                    "filename": err.filename,
+                    "line_number": err.line_number,
+                    "byte_offset": err.byte_offset,
+                    "error": err.kind.to_string(),
+                    "line": String::from_utf8_lossy(line)}),
+            )?;
+            writeln!(file, "")?;
+        }
+        Ok(())
+    }
 }
 
-pub fn get_stem(full_tactic: &str) -> Option<String> {
-    split_tactic(full_tactic).map(|(stem, _args)| stem)
+/// The version of the scrape format written by scraped_to_file. Version
+/// 1 is the old flat layout, with only the focused goal stored in
+/// "prev_hyps" and "prev_goal"; version 2 stores the whole proof
+/// context under "context".
+pub const SCRAPE_SCHEMA_VERSION: u32 = 2;
+
+/// The on-disk layout of a tactic line, which can be either the
+/// legacy flat layout or the current nested one. Older scrapes don't
+/// have a version marker, so the layout is decided by which fields
+/// are present.
+#[derive(Deserialize)]
+struct ScrapedTacticRecord {
+    #[serde(default)]
+    schema_version: Option<u32>,
+    relevant_lemmas: Vec<String>,
+    prev_tactics: Vec<String>,
+    #[serde(default)]
+    context: Option<ProofContext>,
+    #[serde(default)]
+    prev_hyps: Option<Vec<String>>,
+    #[serde(default)]
+    prev_goal: Option<String>,
+    tactic: String,
 }
 
-pub fn preprocess_datum(datum: ScrapedTactic) -> ScrapedTactic {
-    let tacstr = kill_comments(&datum.tactic);
-    let mut newtac = tacstr.trim().to_string();
-    // Truncate semicolons
-    if newtac.chars().next() == Some('(') && newtac.chars().last() == Some(')') {
-        newtac = newtac[1..newtac.len() - 1].to_string();
-    }
-    if let Some((before_semi, _)) = split_to_next_pat_outside_parens(&newtac, ";") {
-        newtac = before_semi.trim().to_string();
-        newtac.push('.');
-    }
-
-    // Substitutions
-    lazy_static! {
-        static ref SUBS_MAP: HashMap<&'static str, &'static str> = {
-            let mut res = HashMap::new();
-            res.insert("auto", "eauto.");
-            res.insert("intros until", "intros.");
-            res.insert("intro", "intros.");
-            res.insert("constructor", "econstructor.");
-            res
+impl ScrapedTacticRecord {
+    fn into_scraped(self) -> serde_json::Result<ScrapedTactic> {
+        use serde::de::Error;
+        if let Some(version) = self.schema_version {
+            if version > SCRAPE_SCHEMA_VERSION {
+                return Err(serde_json::Error::custom(format!(
+                    "scrape schema version {} is newer than the supported version {}",
+                    version, SCRAPE_SCHEMA_VERSION
+                )));
+            }
+        }
+        let context = match (self.context, self.prev_hyps, self.prev_goal) {
+            (Some(context), _, _) => context,
+            (None, hyps, Some(goal)) => ProofContext {
+                fg_goals: vec![Obligation {
+                    hypotheses: hyps.unwrap_or_default(),
+                    goal,
+                }],
+                bg_goals: vec![],
+                shelved_goals: vec![],
+                given_up_goals: vec![],
+            },
+            (None, _, None) => {
+                return Err(serde_json::Error::custom(
+                    "tactic has neither a \"context\" nor a \"prev_goal\" field",
+                ))
+            }
         };
+        Ok(ScrapedTactic {
+            relevant_lemmas: self.relevant_lemmas,
+            prev_tactics: self.prev_tactics,
+            context,
+            tactic: self.tactic,
+            provenance: None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct ScrapedTacticRecordOut<'a> {
+    schema_version: u32,
+    relevant_lemmas: &'a Vec<String>,
+    prev_tactics: &'a Vec<String>,
+    context: &'a ProofContext,
+    tactic: &'a String,
+}
+
+fn parse_scraped_line(line: &str) -> serde_json::Result<ScrapedData> {
+    if line.starts_with("\"") {
+        Ok(ScrapedData::Vernac(VernacCommand {
+            command: serde_json::from_str(line)?,
+        }))
+    } else {
+        serde_json::from_str::<ScrapedTacticRecord>(line)?
+            .into_scraped()
+            .map(ScrapedData::Tactic)
     }
-    if let Some(stem) = get_stem(&newtac) {
-        let borrowed_stem: &str = &stem;
-        if let Some(subbed_stem) = SUBS_MAP.get(borrowed_stem) {
-            newtac = subbed_stem.to_string();
+}
+
+fn write_scraped(mut writer: impl Write, datum: &ScrapedData) -> io::Result<()> {
+    match datum {
+        ScrapedData::Vernac(VernacCommand { command }) => {
+            serde_json::to_writer(&mut writer, command)?
         }
+        ScrapedData::Tactic(tac) => serde_json::to_writer(
+            &mut writer,
+            &ScrapedTacticRecordOut {
+                schema_version: SCRAPE_SCHEMA_VERSION,
+                relevant_lemmas: &tac.relevant_lemmas,
+                prev_tactics: &tac.prev_tactics,
+                context: &tac.context,
+                tactic: &tac.tactic,
+            },
+        )?,
     }
+    writeln!(writer, "")
+}
 
-    // Numeric args
-    if let Some((stem, argstr)) = split_tactic(&newtac) {
-        if stem == "induction" || stem == "destruct" {
-            // println!("Preprocessing {}", datum.tactic);
-            let argstr = if argstr.chars().last() == Some('.') {
-                argstr.chars().take(argstr.len() - 1).collect()
-            } else {
-                argstr
+impl Iterator for ScrapeReader {
+    type Item = Result<ScrapedData, ScrapeError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            let mut buf = Vec::new();
+            let line_offset = self.byte_offset;
+            self.line_number += 1;
+            match self.reader.read_until(b'\n', &mut buf) {
+                Ok(0) => return None,
+                Ok(num_bytes) => self.byte_offset += num_bytes,
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(self.line_error(line_offset, ScrapeErrorKind::Io(err))));
+                }
+            }
+            let parsed = match String::from_utf8(buf) {
+                Ok(line) => parse_scraped_line(line.trim_end())
+                    .map_err(|err| (line.into_bytes(), ScrapeErrorKind::Json(err))),
+                Err(err) => Err((err.as_bytes().to_vec(), ScrapeErrorKind::Utf8(err))),
             };
-            let argstr_tokens: Vec<_> = argstr.split_whitespace().collect();
-            if argstr_tokens.len() == 1 {
-                let new_argstr = argstr_tokens
-                    .into_iter()
-                    .map(|token| match token.parse::<i64>() {
-                        Ok(var_idx) => {
-                            match get_binder_var(datum.context.focused_goal(), var_idx) {
-                                Some(var) => var,
-                                None => token,
-                            }
-                        }
-                        Err(_) => token,
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                newtac = vec![stem, new_argstr].join(" ");
-                newtac.push('.');
+            let (line, kind) = match parsed {
+                Ok(datum) => return Some(Ok(self.with_provenance(datum))),
+                Err(bad) => bad,
+            };
+            let err = self.line_error(line_offset, kind);
+            if let BadLinePolicy::Fail = self.policy {
+                self.failed = true;
+                return Some(Err(err));
+            }
+            if let Err(io_err) = self.record_bad_line(&err, &line) {
+                self.failed = true;
+                let bad_lines_path = match &self.policy {
+                    BadLinePolicy::Collect(path) => path.as_str(),
+                    _ => "",
+                };
+                return Some(Err(scrape_io_error(bad_lines_path, io_err)));
             }
         }
     }
+}
 
-    datum.with_tactic(newtac)
+pub fn scrape_io_error(filename: &str, err: io::Error) -> ScrapeError {
+    ScrapeError {
+        filename: filename.to_string(),
+        line_number: 0,
+        byte_offset: 0,
+        kind: ScrapeErrorKind::Io(err),
+    }
 }
 
-pub fn tactic_takes_hyp_args(tactic_stem: &str) -> bool {
-    let tactic_stem = tactic_stem.trim();
-    if tactic_stem.starts_with("now") || tactic_stem.starts_with("try") {
-        tactic_takes_hyp_args(&tactic_stem[4..])
-    } else if tactic_stem.starts_with("repeat") {
-        tactic_takes_hyp_args(&tactic_stem[7..])
-    } else {
-        tactic_stem == "apply"
-            || tactic_stem == "eapply"
-            || tactic_stem == "eexploit"
-            || tactic_stem == "exploit"
-            || tactic_stem == "erewrite"
-            || tactic_stem == "rewrite"
-            || tactic_stem == "erewrite !"
-            || tactic_stem == "rewrite !"
-            || tactic_stem == "erewrite <-"
-            || tactic_stem == "rewrite <-"
-            || tactic_stem == "destruct"
-            || tactic_stem == "elim"
-            || tactic_stem == "eelim"
-            || tactic_stem == "inversion"
-            || tactic_stem == "monadInv"
-            || tactic_stem == "pattern"
-            || tactic_stem == "revert"
-            || tactic_stem == "exact"
-            || tactic_stem == "eexact"
-            || tactic_stem == "simpl in"
-            || tactic_stem == "fold"
-            || tactic_stem == "generalize"
-            || tactic_stem == "exists"
-            || tactic_stem == "case"
-            || tactic_stem == "inv"
-            || tactic_stem == "subst"
-            || tactic_stem == "specialize"
-    }
-}
-
-pub fn indexed_premises<'a>(premises: impl Iterator<Item = &'a str>) -> Vec<(usize, String)> {
-    let mut result = Vec::new();
-    for (idx, premise) in premises.enumerate() {
-        let before_colon = premise
-            .split(":")
-            .next()
-            .expect("Hyp string doesn't have a colon in it")
-            .trim();
-        let vars = before_colon
-            .split(",")
-            .map(|varname| (idx, varname.trim().to_string()));
-        result.extend(vars);
+pub fn scraped_from_file(
+    filename: &str,
+    policy: BadLinePolicy,
+) -> Result<ScrapeReader, ScrapeError> {
+    let reader = open_maybe_compressed(filename).map_err(|err| scrape_io_error(filename, err))?;
+    let bad_lines_file = match &policy {
+        BadLinePolicy::Collect(path) => {
+            Some(File::create(path).map_err(|err| scrape_io_error(path, err))?)
+        }
+        _ => None,
+    };
+    Ok(ScrapeReader {
+        filename: filename.to_string(),
+        reader,
+        policy,
+        bad_lines_file,
+        line_number: 0,
+        byte_offset: 0,
+        num_bad_lines: 0,
+        shared_count: Default::default(),
+        num_proofs: 0,
+        in_proof: false,
+        failed: false,
+    })
+}
+
+/// Reads just the tactics out of a scrape file, stopping at the first
+/// error.
+pub fn scraped_tactics_iter(
+    reader: impl iter::Iterator<Item = Result<ScrapedData, ScrapeError>>,
+) -> impl iter::Iterator<Item = Result<ScrapedTactic, ScrapeError>> {
+    reader.flat_map(|datum| match datum {
+        Ok(ScrapedData::Vernac(_)) => None,
+        Ok(ScrapedData::Tactic(t)) => Some(Ok(t)),
+        Err(err) => Some(Err(err)),
+    })
+}
+
+/// Writes scraped data in the format that scraped_from_file reads,
+/// compressing it if the filename ends in .gz or .zst.
+pub fn scraped_to_file(
+    filename: &str,
+    scraped: impl iter::Iterator<Item = ScrapedData>,
+) -> io::Result<()> {
+    let mut writer = MaybeCompressedWriter::create(filename)?;
+    for point in scraped {
+        write_scraped(&mut writer, &point)?;
     }
+    writer.finish()
+}
+
+/// The source with its comments removed. Comment delimiters inside
+/// string literals don't count, and an unclosed comment runs to the
+/// end of the source.
+pub fn kill_comments(source: &str) -> String {
+    let mut result = String::new();
+    let mut cur_pos = 0;
+    for comment in lex(source)
+        .into_iter()
+        .filter(|lexeme| lexeme.kind == LexemeKind::Comment)
+    {
+        result.push_str(&source[cur_pos..comment.start]);
+        cur_pos = comment.end;
+    }
+    result.push_str(&source[cur_pos..]);
     result
 }
 
-/// A function for doing some quick & dirty parsing of forall
-/// binders. Ported from the python implementation of this same
-/// function in serapi_instance.py.
-fn get_binder_var(goal: &str, binder_idx: i64) -> Option<&str> {
-    let mut paren_depth = 0;
-    let mut binders_passed = 0;
-    let mut skip = false;
-    lazy_static! {
-        static ref FORALL: Regex = Regex::new(r"forall\s+").unwrap();
+/// The lexemes of a tactic's arguments, as split_tactic returns them,
+/// without the terminating period.
+pub fn arg_tokens(argstr: &str) -> Vec<&str> {
+    let mut tokens: Vec<&str> = get_words(argstr);
+    if tokens.last() == Some(&".") {
+        tokens.pop();
     }
-    let forall_match = match FORALL.find(goal) {
-        Some(m) => m,
-        None => return None,
-    };
-    let rest_goal = &goal[forall_match.end()..];
-    for w in get_symbols(rest_goal) {
-        if w == "(" {
-            paren_depth += 1;
-        } else if w == ")" {
-            paren_depth -= 1;
-            if paren_depth < 2 {
-                skip = false;
+    tokens
+}
+
+/// How many arguments a tactic has, as split_tactic returns them. This
+/// counts whitespace-separated words rather than lexemes, so that
+/// `apply @foo.` and `eapply (H).` have one argument each, as they
+/// always have for the maxargs and minargs filters.
+pub fn arg_count(argstr: &str) -> usize {
+    let trimmed = argstr.trim();
+    trimmed
+        .strip_suffix('.')
+        .unwrap_or(trimmed)
+        .split_whitespace()
+        .count()
+}
+
+pub fn split_tactic(full_tactic: &str) -> Option<(String, String)> {
+    let no_comments_tac = kill_comments(full_tactic);
+    let full_tactic = no_comments_tac.as_str();
+    let lexemes = lex(full_tactic);
+    match command_kind(full_tactic) {
+        CommandKind::Bullet(_) | CommandKind::OpenBrace | CommandKind::CloseBrace => return None,
+        _ => (),
+    }
+    if lexemes.is_empty()
+        || lexemes
+            .iter()
+            .any(|lexeme| lexeme.kind == LexemeKind::Symbol && lexeme.text == ";")
+    {
+        return None;
+    }
+    let first = &lexemes[0];
+    let rest = &full_tactic[first.end..];
+    if ["try", "now", "repeat", "decide"].contains(&first.text) && lexemes.len() > 1 {
+        return split_tactic(rest).map(|(rest_stem, rest_rest)| {
+            let mut new_stem = first.text.to_string();
+            new_stem.push_str(" ");
+            new_stem.push_str(&rest_stem);
+            (new_stem, rest_rest)
+        });
+    }
+    for (stem_start, stem_end) in &[
+        ("rewrite", "<-"),
+        ("rewrite", "!"),
+        ("erewrite", "<-"),
+        ("erewrite", "!"),
+        ("intros", "until"),
+        ("simpl", "in"),
+    ] {
+        if first.text == *stem_start && lexemes.get(1).map(|l| l.text) == Some(*stem_end) {
+            return Some((
+                format!("{} {}", stem_start, stem_end),
+                full_tactic[lexemes[1].end..].to_string(),
+            ));
+        }
+    }
+    match first.kind {
+        LexemeKind::Ident | LexemeKind::QualifiedName | LexemeKind::Keyword => {
+            Some((first.text.to_string(), rest.to_string()))
+        }
+        // Things like goal selectors have no stem.
+        _ => Some((
+            "".to_string(),
+            full_tactic[first.start..].to_string(),
+        )),
+    }
+}
+
+pub fn get_stem(full_tactic: &str) -> Option<String> {
+    split_tactic(full_tactic).map(|(stem, _args)| stem)
+}
+
+/// Preprocesses a tactic given the context after it, which tells the
+/// Linearize step how many goals it created. Returns None for chains
+/// that can't be linearized.
+pub fn preprocess_datum_with_after(
+    config: &PreprocessConfig,
+    datum: ScrapedTactic,
+    after: &ProofContext,
+) -> Option<ScrapedTactic> {
+    let newtac = try_preprocess_tactic(
+        config,
+        &datum.tactic,
+        datum.context.focused_goal(),
+        goals_created(&datum.context, after),
+    )
+    .ok()?;
+    Some(datum.with_tactic(newtac))
+}
+
+/// Preprocesses a tactic without knowing what it did, so chains that
+/// can't be linearized are left as they are.
+pub fn preprocess_tactic(config: &PreprocessConfig, tactic: &str, goal: &str) -> String {
+    try_preprocess_tactic(config, tactic, goal, None).unwrap_or_else(|err| err.tactic)
+}
+
+pub fn try_preprocess_tactic(
+    config: &PreprocessConfig,
+    tactic: &str,
+    goal: &str,
+    goals_created: Option<usize>,
+) -> Result<String, LinearizeError> {
+    let tacstr = kill_comments(tactic);
+    let mut newtac = tacstr.trim().to_string();
+    for step in config.steps.iter() {
+        match step {
+            PreprocessStep::TruncateSemicolons => {
+                if newtac.chars().next() == Some('(') && newtac.chars().last() == Some(')') {
+                    newtac = newtac[1..newtac.len() - 1].to_string();
+                }
+                if let Some((before_semi, _)) = split_to_next_pat_outside_parens(&newtac, ";") {
+                    newtac = before_semi.trim().to_string();
+                    newtac.push('.');
+                }
             }
-        } else if paren_depth < 2 && !skip {
-            if w == ":" {
-                skip = true;
-            } else {
-                binders_passed += 1;
-                if binders_passed == binder_idx {
-                    return Some(w);
+            PreprocessStep::Linearize => {
+                newtac = linearize_tactic(&newtac, goals_created)?.remove(0);
+            }
+            PreprocessStep::Substitute => {
+                if let Some(stem) = get_stem(&newtac) {
+                    if let Some(subbed_stem) = config.substitutions.get(&stem) {
+                        newtac = subbed_stem.to_string();
+                    }
+                }
+            }
+            PreprocessStep::NumericArgs => {
+                if let Some((stem, argstr)) = split_tactic(&newtac) {
+                    if stem == "induction" || stem == "destruct" {
+                        let argstr = if argstr.chars().last() == Some('.') {
+                            argstr.chars().take(argstr.len() - 1).collect()
+                        } else {
+                            argstr
+                        };
+                        let argstr_tokens: Vec<_> = argstr.split_whitespace().collect();
+                        if argstr_tokens.len() == 1 {
+                            let new_argstr = argstr_tokens
+                                .into_iter()
+                                .map(|token| match token.parse::<usize>() {
+                                    // If the premise has no name, or
+                                    // the goal doesn't parse, leave
+                                    // the number alone.
+                                    Ok(premise_num) => match numbered_premise(goal, premise_num) {
+                                        Ok(Some(name)) => name,
+                                        _ => token,
+                                    },
+                                    Err(_) => token,
+                                })
+                                .collect::<Vec<_>>()
+                                .join(" ");
+                            newtac = vec![stem, new_argstr].join(" ");
+                            newtac.push('.');
+                        }
+                    }
                 }
             }
         }
     }
-    panic!("Not enough binders!")
+    Ok(newtac)
 }
 
+pub fn indexed_premises<'a>(
+    premises: impl Iterator<Item = &'a str>,
+) -> Result<Vec<(usize, String)>, HypothesisError> {
+    let mut result = Vec::new();
+    for (idx, premise) in premises.enumerate() {
+        let vars = parse_hypothesis(premise)?
+            .names
+            .into_iter()
+            .map(|varname| (idx, varname.to_string()));
+        result.extend(vars);
+    }
+    Ok(result)
+}
+
+/// The type of a hypothesis, or the whole string if it can't be
+/// parsed as one.
 pub fn get_hyp_type(hyp: &str) -> &str {
-    lazy_static! {
-        static ref TYPECOLON: Regex = Regex::new(r":[^=]").unwrap();
+    match parse_hypothesis(hyp) {
+        Ok(parsed) => parsed.ty,
+        Err(_) => hyp,
     }
-    TYPECOLON.splitn(hyp, 2).last().unwrap_or("")
 }
 
 pub fn symbol_matches(full_symbol: &str, shorthand_symbol: &str) -> bool {
@@ -531,6 +985,15 @@ pub fn symbol_matches(full_symbol: &str, shorthand_symbol: &str) -> bool {
     }
 }
 
+/// Raises a python UserWarning. This takes the GIL, so call it from
+/// the thread that released it rather than from a rayon worker.
+pub fn warn_py(message: &str) -> PyResult<()> {
+    Python::with_gil(|py| {
+        let category = py.import("builtins")?.getattr("UserWarning")?;
+        PyErr::warn(py, category, message, 1)
+    })
+}
+
 #[pyclass]
 #[derive(Default, Clone)]
 pub struct DataloaderArgs {
@@ -549,6 +1012,8 @@ pub struct DataloaderArgs {
     #[pyo3(get, set)]
     pub num_relevance_samples: usize,
     #[pyo3(get, set)]
+    pub max_arity: usize,
+    #[pyo3(get, set)]
     pub keywords_file: String,
     #[pyo3(get, set)]
     pub context_filter: String,
@@ -560,6 +1025,20 @@ pub struct DataloaderArgs {
     pub load_embedding: Option<String>,
     #[pyo3(get, set)]
     pub load_features_state: Option<String>,
+    #[pyo3(get, set)]
+    pub skip_bad_lines: bool,
+    #[pyo3(get, set)]
+    pub bad_lines_file: Option<String>,
+    #[pyo3(get, set)]
+    pub dataset_cache_dir: Option<String>,
+    #[pyo3(get, set)]
+    pub manifest_prelude: Option<String>,
+    #[pyo3(get, set)]
+    pub manifest_split: Option<String>,
+    #[pyo3(get, set)]
+    pub preprocess_config: Option<String>,
+    #[pyo3(get, set)]
+    pub tactic_signatures: Option<String>,
 }
 #[pymethods]
 impl DataloaderArgs {
@@ -570,6 +1049,28 @@ impl DataloaderArgs {
     }
 }
 
+impl DataloaderArgs {
+    pub fn bad_line_policy(&self) -> BadLinePolicy {
+        match &self.bad_lines_file {
+            Some(path) => BadLinePolicy::Collect(path.clone()),
+            None if self.skip_bad_lines => BadLinePolicy::Skip,
+            None => BadLinePolicy::Fail,
+        }
+    }
+    pub fn load_preprocess_config(&self) -> io::Result<PreprocessConfig> {
+        match &self.preprocess_config {
+            Some(path) => PreprocessConfig::load(path),
+            None => Ok(Default::default()),
+        }
+    }
+    pub fn load_tactic_signatures(&self) -> io::Result<TacticSignatures> {
+        match &self.tactic_signatures {
+            Some(path) => TacticSignatures::load(path),
+            None => Ok(Default::default()),
+        }
+    }
+}
+
 pub struct NormalFloat(f64);
 impl NormalFloat {
     pub fn new(v: f64) -> NormalFloat {
@@ -599,3 +1100,271 @@ impl Ord for NormalFloat {
         self.partial_cmp(other).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_tactic(line: &str) -> ScrapedTactic {
+        match parse_scraped_line(line).unwrap() {
+            ScrapedData::Tactic(t) => t,
+            ScrapedData::Vernac(_) => panic!("Parsed a tactic line as a vernac"),
+        }
+    }
+
+    #[test]
+    fn test_scrape_round_trip() {
+        let obl = |goal: &str| Obligation {
+            hypotheses: vec!["H : x = y".to_string()],
+            goal: goal.to_string(),
+        };
+        let tac = ScrapedTactic {
+            relevant_lemmas: vec!["foo : forall n, n = n".to_string()],
+            prev_tactics: vec!["Proof.".to_string()],
+            context: ProofContext {
+                fg_goals: vec![obl("x = y"), obl("y = x")],
+                bg_goals: vec![obl("True")],
+                shelved_goals: vec![obl("nat")],
+                given_up_goals: vec![obl("False")],
+            },
+            tactic: "split.".to_string(),
+            provenance: None,
+        };
+        let mut buf = Vec::new();
+        write_scraped(&mut buf, &ScrapedData::Vernac(VernacCommand {
+            command: "Lemma \"quoted\"\n: x = y.".to_string(),
+        }))
+        .unwrap();
+        write_scraped(&mut buf, &ScrapedData::Tactic(tac.clone())).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&buf).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2);
+        match parse_scraped_line(lines[0]).unwrap() {
+            ScrapedData::Vernac(cmd) => assert_eq!(cmd.command, "Lemma \"quoted\"\n: x = y."),
+            ScrapedData::Tactic(_) => panic!("Parsed a vernac line as a tactic"),
+        }
+        assert_eq!(parse_tactic(lines[1]), tac);
+    }
+
+    #[test]
+    fn test_compressed_scrape_files() {
+        let data = vec![
+            ScrapedData::Vernac(VernacCommand {
+                command: "Lemma foo : True.".to_string(),
+            }),
+            ScrapedData::Tactic(ScrapedTactic {
+                relevant_lemmas: vec![],
+                prev_tactics: vec![],
+                context: ProofContext::empty(),
+                tactic: "auto.".to_string(),
+                provenance: None,
+            }),
+        ];
+        for ext in &["scrape", "scrape.gz", "scrape.zst"] {
+            let path = std::env::temp_dir()
+                .join(format!("dataloader-test-{}.{}", std::process::id(), ext));
+            let path = path.to_str().unwrap();
+            scraped_to_file(path, data.iter().cloned()).unwrap();
+            let tactics: Vec<ScrapedTactic> =
+                scraped_tactics_iter(scraped_from_file(path, BadLinePolicy::Fail).unwrap())
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(tactics.len(), 1);
+            assert_eq!(tactics[0].tactic, "auto.");
+        }
+    }
+
+    #[test]
+    fn test_partial_transitions() {
+        let tactic = |tac: &str, num_goals: usize| ScrapedTactic {
+            relevant_lemmas: vec![],
+            prev_tactics: vec![],
+            context: ProofContext {
+                fg_goals: (0..num_goals)
+                    .map(|_| Obligation {
+                        hypotheses: vec![],
+                        goal: "True".to_string(),
+                    })
+                    .collect(),
+                ..ProofContext::empty()
+            },
+            tactic: tac.to_string(),
+            provenance: None,
+        };
+        let proof = ScrapedProof {
+            statement: None,
+            tactics: vec![
+                tactic("intros; split.", 1),
+                tactic("exact I; idtac.", 2),
+                tactic("exact I.", 1),
+            ],
+            closing_command: None,
+        };
+        let summary = |config: &PreprocessConfig| -> Vec<(String, bool)> {
+            proof
+                .transitions(config)
+                .into_iter()
+                .map(|transition| (transition.tactic, transition.partial))
+                .collect()
+        };
+        let mut config = PreprocessConfig::default();
+        assert_eq!(
+            summary(&config),
+            vec![
+                ("intros.".to_string(), true),
+                ("exact I.".to_string(), false),
+                ("exact I.".to_string(), false),
+            ]
+        );
+        config.steps = vec![];
+        assert!(summary(&config).iter().all(|(_, partial)| !partial));
+    }
+
+    #[test]
+    fn test_bad_line_policies() {
+        let good = r#"{"relevant_lemmas": [], "prev_tactics": [], "prev_goal": "True", "#
+            .to_string()
+            + r#""tactic": "auto."}"#;
+        let contents = format!("{}\nnot json\n{}\n", good, good);
+        let tmp = |name: &str| {
+            let path = std::env::temp_dir()
+                .join(format!("dataloader-test-{}-{}", std::process::id(), name));
+            path.to_str().unwrap().to_string()
+        };
+        let path = tmp("bad-lines.scrape");
+        std::fs::write(&path, &contents).unwrap();
+
+        let results: Vec<_> = scraped_from_file(&path, BadLinePolicy::Fail).unwrap().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        match &results[1] {
+            Err(err) => assert_eq!((err.line_number, err.byte_offset), (2, good.len() + 1)),
+            Ok(_) => panic!("Read a bad line under the Fail policy"),
+        }
+
+        let count = BadLineCount::default();
+        let mut reader = scraped_from_file(&path, BadLinePolicy::Skip)
+            .unwrap()
+            .counting_into(&count);
+        assert_eq!(reader.by_ref().filter(|datum| datum.is_ok()).count(), 2);
+        assert_eq!((reader.num_bad_lines(), count.get()), (1, 1));
+
+        let bad_lines_path = tmp("bad-lines.json");
+        let reader = scraped_from_file(&path, BadLinePolicy::Collect(bad_lines_path.clone()));
+        assert_eq!(reader.unwrap().filter(|datum| datum.is_ok()).count(), 2);
+        let collected = std::fs::read_to_string(&bad_lines_path).unwrap();
+        std::fs::remove_file(&bad_lines_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = collected
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["filename"], path.as_str());
+        assert_eq!(lines[0]["line_number"], 2);
+        assert_eq!(lines[0]["byte_offset"], good.len() + 1);
+        assert_eq!(lines[0]["line"], "not json\n");
+    }
+
+    #[test]
+    fn test_preprocess_tactic() {
+        let config = PreprocessConfig::default();
+        let preprocess = |tactic, goal| preprocess_tactic(&config, tactic, goal);
+        assert_eq!(preprocess("induction 1.", "forall n (H : n > 0), P n"), "induction H.");
+        assert_eq!(preprocess("destruct 2.", "forall x y, x = y"), "destruct 2.");
+        assert_eq!(preprocess("destruct 1.", "forall (x : nat, P"), "destruct 1.");
+
+        let config = PreprocessConfig {
+            steps: vec![PreprocessStep::Linearize],
+            substitutions: Default::default(),
+        };
+        assert_eq!(
+            try_preprocess_tactic(&config, "(intros; simpl); lia.", "True", Some(0)).unwrap(),
+            "intros."
+        );
+        assert!(try_preprocess_tactic(&config, "destruct H; auto.", "True", Some(0)).is_err());
+        assert_eq!(preprocess_tactic(&config, "destruct H; auto.", "True"), "destruct H; auto.");
+    }
+
+    #[test]
+    fn test_split_tactic() {
+        assert_eq!(
+            kill_comments("idtac \"(*\". (* x *) auto *)."),
+            "idtac \"(*\".  auto *)."
+        );
+        assert_eq!(
+            split_tactic("try rewrite  <- (* why *) Nat.add_comm."),
+            Some(("try rewrite <-".to_string(), "  Nat.add_comm.".to_string()))
+        );
+        assert_eq!(split_tactic("- {"), None);
+        assert_eq!(arg_tokens(" H1 H2."), vec!["H1", "H2"]);
+        assert_eq!(arg_tokens(" @foo."), vec!["@", "foo"]);
+        assert_eq!(arg_count(" @foo."), 1);
+        assert_eq!(arg_count(" (H)."), 1);
+        assert_eq!(arg_count(" H1  H2 ."), 2);
+        assert_eq!(arg_count("."), 0);
+    }
+
+    #[test]
+    fn test_read_legacy_scrape() {
+        let tac = parse_tactic(
+            r#"{"prev_tactics": ["Proof."], "prev_hyps": ["H : P"], "prev_goal": "P",
+                "relevant_lemmas": [], "tactic": "exact H."}"#,
+        );
+        assert_eq!(tac.context.focused_goal(), "P");
+        assert_eq!(tac.context.focused_hyps(), &vec!["H : P".to_string()]);
+        assert!(parse_scraped_line(
+            r#"{"schema_version": 99, "prev_tactics": [], "relevant_lemmas": [],
+                "prev_goal": "P", "tactic": "auto."}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_proof_grouping() {
+        let vernac = |cmd: &str| {
+            Ok::<_, ScrapeError>(ScrapedData::Vernac(VernacCommand {
+                command: cmd.to_string(),
+            }))
+        };
+        let tactic = |tac: &str| {
+            Ok(ScrapedData::Tactic(parse_tactic(&format!(
+                "{{\"relevant_lemmas\": [], \"prev_tactics\": [], \"prev_goal\": \"True\", \
+                 \"tactic\": \"{}\"}}",
+                tac
+            ))))
+        };
+        let data = vec![
+            vernac("Lemma foo : True."),
+            tactic("Proof."),
+            tactic("auto."),
+            tactic("Qed."),
+            vernac("Lemma bar : True."),
+            tactic("Proof."),
+            vernac("Admitted."),
+            vernac("Lemma baz : True."),
+            tactic("trivial."),
+        ];
+        let proofs: Vec<ScrapedProof> =
+            scraped_proofs_iter(data.into_iter()).collect::<Result<_, _>>().unwrap();
+        let summary: Vec<(Option<&str>, usize, Option<&str>)> = proofs
+            .iter()
+            .map(|p| {
+                (
+                    p.statement.as_deref(),
+                    p.tactics.len(),
+                    p.closing_command.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("Lemma foo : True."), 3, Some("Qed.")),
+                (Some("Lemma bar : True."), 1, Some("Admitted.")),
+                (Some("Lemma baz : True."), 1, None),
+            ]
+        );
+        assert_eq!(proofs[0].transitions(&Default::default()).len(), 3);
+    }
+}
@@ -0,0 +1,125 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use std::cell::OnceCell;
+
+use crate::command_kind::{command_kind, CommandKind};
+use crate::context_delta::{context_delta, ContextDelta};
+use crate::scraped_data::*;
+use crate::tokenizer::get_words;
+
+/// The parts of a sample that context filters look at. Each part is
+/// computed the first time a filter asks for it, and then shared by
+/// every other filter applied to the same sample.
+pub struct TacticAnalysis<'a> {
+    pub scraped: &'a ScrapedTactic,
+    /// The context after the tactic, for transitions
+    pub after: Option<&'a ProofContext>,
+    code: OnceCell<String>,
+    split: OnceCell<Option<(String, Vec<String>, usize)>>,
+    command_kind: OnceCell<CommandKind>,
+    goal_words: OnceCell<Vec<&'a str>>,
+    hyp_names: OnceCell<Option<Vec<String>>>,
+    lemma_names: OnceCell<Option<Vec<String>>>,
+    delta: OnceCell<ContextDelta>,
+}
+
+fn premise_names<'a>(premises: impl Iterator<Item = &'a str>) -> Option<Vec<String>> {
+    indexed_premises(premises)
+        .ok()
+        .map(|premises| premises.into_iter().map(|(_idx, name)| name).collect())
+}
+
+impl<'a> TacticAnalysis<'a> {
+    pub fn new(scraped: &'a ScrapedTactic, after: Option<&'a ProofContext>) -> Self {
+        TacticAnalysis {
+            scraped,
+            after,
+            code: OnceCell::new(),
+            split: OnceCell::new(),
+            command_kind: OnceCell::new(),
+            goal_words: OnceCell::new(),
+            hyp_names: OnceCell::new(),
+            lemma_names: OnceCell::new(),
+            delta: OnceCell::new(),
+        }
+    }
+    /// The tactic without its comments
+    pub fn code(&self) -> &str {
+        self.code.get_or_init(|| kill_comments(&self.scraped.tactic))
+    }
+    fn split(&self) -> Option<&(String, Vec<String>, usize)> {
+        self.split
+            .get_or_init(|| {
+                split_tactic(&self.scraped.tactic).map(|(stem, argstr)| {
+                    let args = arg_tokens(&argstr).into_iter().map(String::from).collect();
+                    (stem, args, arg_count(&argstr))
+                })
+            })
+            .as_ref()
+    }
+    /// The stem of the tactic and its argument tokens, or None if it
+    /// can't be split (see split_tactic).
+    pub fn stem_and_args(&self) -> Option<(&str, &[String])> {
+        self.split()
+            .map(|(stem, args, _count)| (stem.as_str(), args.as_slice()))
+    }
+    /// How many arguments the tactic has, as arg_count counts them, or
+    /// None if it can't be split.
+    pub fn arg_count(&self) -> Option<usize> {
+        self.split().map(|(_stem, _args, count)| *count)
+    }
+    pub fn command_kind(&self) -> &CommandKind {
+        self.command_kind
+            .get_or_init(|| command_kind(&self.scraped.tactic))
+    }
+    /// The words of the focused goal
+    pub fn goal_words(&self) -> &[&'a str] {
+        self.goal_words
+            .get_or_init(|| get_words(self.scraped.context.focused_goal()))
+    }
+    /// The names bound by the focused hypotheses, or None if one of
+    /// them doesn't parse.
+    pub fn hyp_names(&self) -> Option<&[String]> {
+        self.hyp_names
+            .get_or_init(|| {
+                premise_names(self.scraped.context.focused_hyps().iter().map(|s| s.as_ref()))
+            })
+            .as_deref()
+    }
+    /// The names of the relevant lemmas, or None if one of them doesn't
+    /// parse.
+    pub fn lemma_names(&self) -> Option<&[String]> {
+        self.lemma_names
+            .get_or_init(|| premise_names(self.scraped.relevant_lemmas.iter().map(|s| s.as_ref())))
+            .as_deref()
+    }
+    /// What the tactic changed. This panics for samples without an
+    /// after context.
+    pub fn delta(&self) -> &ContextDelta {
+        self.delta.get_or_init(|| {
+            let after = self
+                .after
+                .expect("Transition filters need the context after the tactic");
+            context_delta(&self.scraped.context, after)
+        })
+    }
+}
@@ -0,0 +1,311 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use lalrpop_util::lalrpop_mod;
+use std::fmt;
+
+use crate::scraped_data::kill_comments;
+
+lalrpop_mod!(tactic_parser);
+
+/// A parsed Ltac expression. This covers the subset of Ltac that
+/// shows up in most proof scripts: sequencing with ";" (and
+/// "; [ t1 | t2 ]"), the try/repeat/progress/now/do combinators,
+/// "||", first and solve, goal selectors, and atomic tactics with
+/// in/with/as/at/using/by clauses. Anything else, like match goal,
+/// fails to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TacticAST {
+    Atomic(AtomicTactic),
+    Then(Box<TacticAST>, Box<TacticAST>),
+    ThenBranches(Box<TacticAST>, Vec<TacticAST>),
+    Try(Box<TacticAST>),
+    Repeat(Box<TacticAST>),
+    Progress(Box<TacticAST>),
+    Now(Box<TacticAST>),
+    Do(usize, Box<TacticAST>),
+    OrElse(Box<TacticAST>, Box<TacticAST>),
+    First(Vec<TacticAST>),
+    Solve(Vec<TacticAST>),
+    /// A goal selector, like "2: auto"; None selects all goals
+    Select(Option<usize>, Box<TacticAST>),
+}
+
+/// A single tactic like "rewrite <- H in H0". Arguments are kept as
+/// source text, one entry per token or bracketed group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtomicTactic {
+    pub stem: String,
+    pub args: Vec<String>,
+    pub clauses: Vec<TacticClause>,
+    pub by_tactic: Option<Box<TacticAST>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TacticClause {
+    In(Vec<String>),
+    With(Vec<String>),
+    As(Vec<String>),
+    At(Vec<String>),
+    Using(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TacticParseError {
+    pub tactic: String,
+    pub msg: String,
+}
+
+impl fmt::Display for TacticParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't parse tactic \"{}\": {}", self.tactic, self.msg)
+    }
+}
+
+impl std::error::Error for TacticParseError {}
+
+/// Parses a tactic, with or without its terminating period.
+pub fn parse_tactic(tactic: &str) -> Result<TacticAST, TacticParseError> {
+    let no_comments = kill_comments(tactic);
+    tactic_parser::TacticParser::new()
+        .parse(&no_comments)
+        .map_err(|err| TacticParseError {
+            tactic: tactic.to_string(),
+            msg: err.to_string(),
+        })
+}
+
+impl AtomicTactic {
+    pub fn idtac() -> Self {
+        AtomicTactic {
+            stem: "idtac".to_string(),
+            args: vec![],
+            clauses: vec![],
+            by_tactic: None,
+        }
+    }
+}
+
+impl TacticAST {
+    /// Whether the expression runs one tactic after another with
+    /// ";", anywhere inside of it.
+    pub fn has_sequencing(&self) -> bool {
+        match self {
+            TacticAST::Then(_, _) | TacticAST::ThenBranches(_, _) => true,
+            TacticAST::Atomic(atomic) => match &atomic.by_tactic {
+                Some(by_tactic) => by_tactic.has_sequencing(),
+                None => false,
+            },
+            TacticAST::OrElse(first, second) => first.has_sequencing() || second.has_sequencing(),
+            TacticAST::First(branches) | TacticAST::Solve(branches) => {
+                branches.iter().any(|branch| branch.has_sequencing())
+            }
+            TacticAST::Try(body)
+            | TacticAST::Repeat(body)
+            | TacticAST::Progress(body)
+            | TacticAST::Now(body)
+            | TacticAST::Do(_, body)
+            | TacticAST::Select(_, body) => body.has_sequencing(),
+        }
+    }
+
+    // The grammar level each expression parses at, so that printing
+    // can add back the parentheses it needs.
+    fn level(&self) -> usize {
+        match self {
+            TacticAST::Atomic(atomic) if atomic.by_tactic.is_some() => 2,
+            TacticAST::Atomic(_) | TacticAST::First(_) | TacticAST::Solve(_) => 1,
+            TacticAST::OrElse(_, _) => 2,
+            TacticAST::Try(_)
+            | TacticAST::Repeat(_)
+            | TacticAST::Progress(_)
+            | TacticAST::Now(_)
+            | TacticAST::Do(_, _) => 3,
+            TacticAST::Then(_, _) | TacticAST::ThenBranches(_, _) => 4,
+            TacticAST::Select(_, _) => 5,
+        }
+    }
+    fn fmt_at(&self, f: &mut fmt::Formatter, level: usize) -> fmt::Result {
+        if self.level() > level {
+            write!(f, "(")?;
+            self.fmt_at(f, self.level())?;
+            return write!(f, ")");
+        }
+        fn fmt_branches(f: &mut fmt::Formatter, branches: &[TacticAST]) -> fmt::Result {
+            write!(f, "[")?;
+            for (idx, branch) in branches.iter().enumerate() {
+                if idx > 0 {
+                    write!(f, " |")?;
+                }
+                write!(f, " ")?;
+                branch.fmt_at(f, 4)?;
+            }
+            write!(f, " ]")
+        }
+        match self {
+            TacticAST::Atomic(atomic) => {
+                write!(f, "{}", atomic.stem)?;
+                for arg in atomic.args.iter() {
+                    write!(f, " {}", arg)?;
+                }
+                for clause in atomic.clauses.iter() {
+                    let (keyword, args) = match clause {
+                        TacticClause::In(args) => ("in", args),
+                        TacticClause::With(args) => ("with", args),
+                        TacticClause::As(args) => ("as", args),
+                        TacticClause::At(args) => ("at", args),
+                        TacticClause::Using(args) => ("using", args),
+                    };
+                    write!(f, " {} {}", keyword, args.join(" "))?;
+                }
+                if let Some(by_tactic) = &atomic.by_tactic {
+                    write!(f, " by ")?;
+                    by_tactic.fmt_at(f, 3)?;
+                }
+                Ok(())
+            }
+            TacticAST::Then(first, second) => {
+                first.fmt_at(f, 4)?;
+                write!(f, "; ")?;
+                second.fmt_at(f, 3)
+            }
+            TacticAST::ThenBranches(first, branches) => {
+                first.fmt_at(f, 4)?;
+                write!(f, "; ")?;
+                fmt_branches(f, branches)
+            }
+            TacticAST::Try(body) => {
+                write!(f, "try ")?;
+                body.fmt_at(f, 3)
+            }
+            TacticAST::Repeat(body) => {
+                write!(f, "repeat ")?;
+                body.fmt_at(f, 3)
+            }
+            TacticAST::Progress(body) => {
+                write!(f, "progress ")?;
+                body.fmt_at(f, 3)
+            }
+            TacticAST::Now(body) => {
+                write!(f, "now ")?;
+                body.fmt_at(f, 3)
+            }
+            TacticAST::Do(n, body) => {
+                write!(f, "do {} ", n)?;
+                body.fmt_at(f, 3)
+            }
+            TacticAST::OrElse(first, second) => {
+                first.fmt_at(f, 1)?;
+                write!(f, " || ")?;
+                second.fmt_at(f, 3)
+            }
+            TacticAST::First(branches) => {
+                write!(f, "first ")?;
+                fmt_branches(f, branches)
+            }
+            TacticAST::Solve(branches) => {
+                write!(f, "solve ")?;
+                fmt_branches(f, branches)
+            }
+            TacticAST::Select(selector, body) => {
+                match selector {
+                    Some(n) => write!(f, "{}: ", n)?,
+                    None => write!(f, "all: ")?,
+                }
+                body.fmt_at(f, 4)
+            }
+        }
+    }
+}
+
+/// Prints the tactic back out as Ltac, without a terminating period.
+impl fmt::Display for TacticAST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_at(f, 5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atomic(stem: &str, args: &[&str]) -> TacticAST {
+        TacticAST::Atomic(AtomicTactic {
+            stem: stem.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            clauses: vec![],
+            by_tactic: None,
+        })
+    }
+
+    #[test]
+    fn test_parse_tactic() {
+        assert_eq!(
+            parse_tactic("induction n; simpl; [ auto | rewrite <- IHn ].").unwrap(),
+            TacticAST::ThenBranches(
+                Box::new(TacticAST::Then(
+                    Box::new(atomic("induction", &["n"])),
+                    Box::new(atomic("simpl", &[]))
+                )),
+                vec![atomic("auto", &[]), atomic("rewrite", &["<-", "IHn"])]
+            )
+        );
+        assert_eq!(
+            parse_tactic("apply Nat.add_comm with (n := S m) in H as [x [y z]].").unwrap(),
+            TacticAST::Atomic(AtomicTactic {
+                stem: "apply".to_string(),
+                args: vec!["Nat.add_comm".to_string()],
+                clauses: vec![
+                    TacticClause::With(vec!["(n := S m)".to_string()]),
+                    TacticClause::In(vec!["H".to_string()]),
+                    TacticClause::As(vec!["[x [y z]]".to_string()]),
+                ],
+                by_tactic: None,
+            })
+        );
+        match parse_tactic("assert (H : x = y) by (try lia || auto).").unwrap() {
+            TacticAST::Atomic(AtomicTactic {
+                by_tactic: Some(by_tactic),
+                ..
+            }) => assert!(matches!(*by_tactic, TacticAST::Try(_))),
+            other => panic!("Parsed as {:?}", other),
+        }
+        assert!(matches!(
+            parse_tactic("2: now (intros; auto).").unwrap(),
+            TacticAST::Select(Some(2), _)
+        ));
+        assert!(parse_tactic("try (split; auto)").unwrap().has_sequencing());
+        assert!(!parse_tactic("rewrite (foo (fun x => x; y))").unwrap().has_sequencing());
+        assert!(parse_tactic("match goal with | H : _ |- _ => auto end.").is_err());
+    }
+
+    #[test]
+    fn test_print_tactic() {
+        for tactic in &[
+            "induction n; simpl; [ auto | rewrite <- IHn ]",
+            "try (split; auto)",
+            "(assert P by auto) || first [ lia | idtac ]",
+            "destruct H as [x|y] eqn : E; [ idtac | eauto ]",
+        ] {
+            assert_eq!(&parse_tactic(tactic).unwrap().to_string(), tactic);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+/* *********************************************************************** */
+//
+//    This file is part of Proverbot9001.
+//
+//    Proverbot9001 is free software: you can redistribute it and/or modify
+//    it under the terms of the GNU General Public License as published by
+//    the Free Software Foundation, either version 3 of the License, or
+//    (at your option) any later version.
+//
+//    Proverbot9001 is distributed in the hope that it will be useful,
+//    but WITHOUT ANY WARRANTY; without even the implied warranty of
+//    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//    GNU General Public License for more details.
+//
+//    You should have received a copy of the GNU General Public License
+//    along with Proverbot9001.  If not, see <https://www.gnu.org/licenses/>.
+//
+//    Copyright 2019 Alex Sanchez-Stern and Yousef Alhessi
+//
+/* *********************************************************************** */
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// What an argument to a tactic can stand for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgKind {
+    /// The name of a hypothesis in the local context
+    Hypothesis,
+    /// The name of a lemma from the environment
+    Lemma,
+    /// A subterm of the focused goal
+    GoalSubterm,
+    /// A new name, which doesn't refer to anything yet
+    FreshIdent,
+    /// An intro pattern like [x | y]
+    IntroPattern,
+    /// A numeral, like the 2 in "induction 2"
+    Numeral,
+    /// An arbitrary term, which may mention any of the above
+    Term,
+}
+
+/// The kinds of argument a tactic accepts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TacticSignature {
+    pub kinds: Vec<ArgKind>,
+}
+
+impl TacticSignature {
+    pub fn accepts(&self, kind: ArgKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+/// Maps tactic stems, as split_tactic produces them, to their
+/// signatures. Files map each stem to a list of kinds, like
+///
+///     monadInv = ["hypothesis"]
+///
+/// and add to (or replace entries in) the built-in table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TacticSignatures {
+    signatures: HashMap<String, TacticSignature>,
+}
+
+const BUILTIN_SIGNATURES: &[(&str, &[ArgKind])] = {
+    use ArgKind::*;
+    &[
+        ("apply", &[Hypothesis, Lemma, Term]),
+        ("eapply", &[Hypothesis, Lemma, Term]),
+        ("exploit", &[Hypothesis, Lemma, Term]),
+        ("eexploit", &[Hypothesis, Lemma, Term]),
+        ("exact", &[Hypothesis, Lemma, Term]),
+        ("eexact", &[Hypothesis, Lemma, Term]),
+        ("elim", &[Hypothesis, Lemma, Term]),
+        ("eelim", &[Hypothesis, Lemma, Term]),
+        ("case", &[Hypothesis, Lemma, Term]),
+        ("destruct", &[Hypothesis, Lemma, Term]),
+        ("specialize", &[Hypothesis, Lemma, Term]),
+        ("generalize", &[Hypothesis, Lemma, Term]),
+        ("pattern", &[Hypothesis, Lemma, Term]),
+        ("fold", &[Hypothesis, Lemma, Term]),
+        ("rewrite", &[Hypothesis, Lemma]),
+        ("erewrite", &[Hypothesis, Lemma]),
+        ("rewrite !", &[Hypothesis, Lemma]),
+        ("erewrite !", &[Hypothesis, Lemma]),
+        ("rewrite <-", &[Hypothesis, Lemma]),
+        ("erewrite <-", &[Hypothesis, Lemma]),
+        ("inversion", &[Hypothesis]),
+        ("inv", &[Hypothesis]),
+        ("monadInv", &[Hypothesis]),
+        ("revert", &[Hypothesis]),
+        ("subst", &[Hypothesis]),
+        ("simpl in", &[Hypothesis]),
+        ("exists", &[Term]),
+        ("induction", &[GoalSubterm, Numeral]),
+        ("intro", &[FreshIdent, IntroPattern]),
+        ("intros", &[FreshIdent, IntroPattern]),
+    ]
+};
+
+impl Default for TacticSignatures {
+    fn default() -> Self {
+        TacticSignatures {
+            signatures: BUILTIN_SIGNATURES
+                .iter()
+                .map(|(stem, kinds)| {
+                    (
+                        stem.to_string(),
+                        TacticSignature {
+                            kinds: kinds.to_vec(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn invalid_signatures(path: &str, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid tactic signatures {}: {}", path, err),
+    )
+}
+
+impl TacticSignatures {
+    /// Loads signatures from a .toml file, or from json otherwise, on
+    /// top of the built-in ones.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let extra: HashMap<String, TacticSignature> = if path.ends_with(".toml") {
+            toml::from_str(&contents).map_err(|err| invalid_signatures(path, err))?
+        } else {
+            serde_json::from_str(&contents).map_err(|err| invalid_signatures(path, err))?
+        };
+        let mut result = Self::default();
+        result.signatures.extend(extra);
+        Ok(result)
+    }
+    /// The signature for a stem, looking through the tacticals that
+    /// don't change what the arguments mean.
+    pub fn get(&self, tactic_stem: &str) -> Option<&TacticSignature> {
+        let mut stem = tactic_stem.trim();
+        while let Some(rest) = ["now ", "try ", "repeat "]
+            .iter()
+            .find_map(|prefix| stem.strip_prefix(prefix))
+        {
+            stem = rest.trim_start();
+        }
+        self.signatures.get(stem)
+    }
+    /// Whether the arguments of a tactic can name hypotheses. Tactics
+    /// we don't know about are assumed not to.
+    pub fn takes_hyp_args(&self, tactic_stem: &str) -> bool {
+        self.get(tactic_stem).map_or(false, |sig| {
+            sig.accepts(ArgKind::Hypothesis) || sig.accepts(ArgKind::Term)
+        })
+    }
+    /// Whether the arguments of a tactic can name lemmas. Tactics we
+    /// don't know about are assumed not to.
+    pub fn takes_lemma_args(&self, tactic_stem: &str) -> bool {
+        self.get(tactic_stem)
+            .map_or(false, |sig| sig.accepts(ArgKind::Lemma) || sig.accepts(ArgKind::Term))
+    }
+    /// Whether the arguments of a tactic can be goal subterms. Unlike
+    /// the above, tactics we don't know about are assumed to.
+    pub fn takes_goal_args(&self, tactic_stem: &str) -> bool {
+        self.get(tactic_stem).map_or(true, |sig| {
+            sig.accepts(ArgKind::GoalSubterm) || sig.accepts(ArgKind::Term)
+        })
+    }
+    /// Whether the arguments of a tactic introduce new names, in
+    /// which case they might look like hypothesis or goal arguments
+    /// without actually being either.
+    pub fn binds_fresh_names(&self, tactic_stem: &str) -> bool {
+        self.get(tactic_stem).map_or(false, |sig| {
+            sig.accepts(ArgKind::FreshIdent) || sig.accepts(ArgKind::IntroPattern)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_signatures() {
+        let defaults = TacticSignatures::default();
+        assert!(defaults.takes_hyp_args("try rewrite <-"));
+        assert!(!defaults.takes_goal_args("rewrite"));
+        assert!(defaults.takes_goal_args("unfold"));
+        assert!(defaults.binds_fresh_names("intros"));
+        assert!(!defaults.takes_hyp_args("lia"));
+
+        let path = std::env::temp_dir().join(format!("signatures-{}.toml", std::process::id()));
+        fs::write(&path, "lia = [\"hypothesis\"]\nrewrite = [\"lemma\"]\n").unwrap();
+        let loaded = TacticSignatures::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.takes_hyp_args("lia"));
+        assert!(!loaded.takes_hyp_args("rewrite"));
+        assert!(loaded.takes_lemma_args("rewrite"));
+        assert!(loaded.binds_fresh_names("intro"));
+    }
+}
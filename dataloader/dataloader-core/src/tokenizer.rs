@@ -1,4 +1,5 @@
 use pyo3::prelude::*;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
@@ -7,8 +8,7 @@ use std::hash::Hash;
 use std::io::prelude::*;
 use std::io::{self, BufRead};
 
-extern crate regex;
-use regex::Regex;
+use crate::coq_lexer::{lex_code, Lexeme, LexemeKind};
 
 pub type Token = i64;
 
@@ -91,6 +91,14 @@ where
     }
 }
 
+/// How tokenizers split sentences into words. Version 1 split them
+/// with a regex that made qualified names like Nat.add three words;
+/// version 2 uses the Coq lexer, which keeps them as one. Tokenizers
+/// from metadata saved before there were versions are version 1, so
+/// existing models get the tokens they were trained on. Retrain them
+/// to get the new tokenization.
+pub const TOKENIZER_VERSION: u32 = 2;
+
 #[pyclass]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Tokenizer {
@@ -98,9 +106,46 @@ pub struct Tokenizer {
     num_reserved_tokens: usize,
     unknown_token: Token,
     token_dict: HashMap<String, Token>,
+    version: u32,
+}
+
+/// The fields Tokenizer had before it was versioned, for reading
+/// tokenizers serialized back then.
+#[derive(Deserialize)]
+struct UnversionedTokenizer {
+    use_unknowns: bool,
+    num_reserved_tokens: usize,
+    unknown_token: Token,
+    token_dict: HashMap<String, Token>,
+}
+
+#[derive(Clone)]
+pub struct PickleableTokenizer(
+    pub bool,
+    pub usize,
+    pub Token,
+    pub HashMap<String, Token>,
+    pub u32,
+);
+
+/// In python, a tokenizer is a tuple of its fields. Tokenizers pickled
+/// before the version was recorded have only the first four, and are
+/// version 1.
+impl<'source> FromPyObject<'source> for PickleableTokenizer {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        if let Ok((use_unknowns, num_reserved, unknown, tokens, version)) = obj.extract() {
+            return Ok(PickleableTokenizer(use_unknowns, num_reserved, unknown, tokens, version));
+        }
+        let (use_unknowns, num_reserved, unknown, tokens) = obj.extract()?;
+        Ok(PickleableTokenizer(use_unknowns, num_reserved, unknown, tokens, 1))
+    }
 }
 
-pub type PickleableTokenizer = (bool, usize, Token, HashMap<String, Token>);
+impl IntoPy<PyObject> for PickleableTokenizer {
+    fn into_py(self, py: Python) -> PyObject {
+        (self.0, self.1, self.2, self.3, self.4).into_py(py)
+    }
+}
 
 impl Tokenizer {
     pub fn new(use_unknowns: bool, num_reserved_tokens: usize, keywords_filepath: &str) -> Self {
@@ -111,6 +156,13 @@ impl Tokenizer {
         .lines()
         .map(|keyword| keyword.unwrap())
         .collect();
+        Self::from_keywords(use_unknowns, num_reserved_tokens, keywords)
+    }
+    pub fn from_keywords(
+        use_unknowns: bool,
+        num_reserved_tokens: usize,
+        keywords: Vec<String>,
+    ) -> Self {
         let first_token = (num_reserved_tokens) as i64;
         let unknown_token = if use_unknowns {
             num_reserved_tokens + keywords.len()
@@ -126,30 +178,79 @@ impl Tokenizer {
             num_reserved_tokens,
             unknown_token,
             token_dict,
+            version: TOKENIZER_VERSION,
         }
     }
+    /// Reads a tokenizer serialized with bincode, including ones
+    /// serialized before tokenizers had versions.
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes).or_else(|err| {
+            let old: UnversionedTokenizer = bincode::deserialize(bytes).map_err(|_| err)?;
+            Ok(Tokenizer {
+                use_unknowns: old.use_unknowns,
+                num_reserved_tokens: old.num_reserved_tokens,
+                unknown_token: old.unknown_token,
+                token_dict: old.token_dict,
+                version: 1,
+            })
+        })
+    }
     pub fn tokenize(&self, sentence: &str) -> Vec<Token> {
-        let words = get_symbols(sentence);
-        words
-            .into_iter()
-            .flat_map(|word| match self.token_dict.get(word) {
-                None => {
-                    if self.use_unknowns {
-                        Some(self.unknown_token)
-                    } else {
-                        None
-                    }
-                }
-                Some(tok) => Some(*tok),
+        self.tokenize_aligned(sentence).tokens
+    }
+    /// Tokenizes a sentence, keeping track of which word each token
+    /// came from. Without unknown tokens, words that aren't keywords
+    /// have no token, so positions in the two sequences can differ.
+    pub fn tokenize_aligned<'a>(&self, sentence: &'a str) -> AlignedTokens<'a> {
+        let words = match self.version {
+            1 => unversioned_words(sentence),
+            _ => lex_code(sentence),
+        };
+        let mut tokens = Vec::new();
+        let mut token_to_word = Vec::new();
+        let word_to_token = words
+            .iter()
+            .enumerate()
+            .map(|(word_idx, word)| {
+                let token = match self.lookup_lexeme(word) {
+                    Some(token) => *token,
+                    None if self.use_unknowns => self.unknown_token,
+                    None => return None,
+                };
+                tokens.push(token);
+                token_to_word.push(word_idx);
+                Some(tokens.len() - 1)
             })
-            .collect()
+            .collect();
+        AlignedTokens {
+            words,
+            tokens,
+            word_to_token,
+            token_to_word,
+        }
+    }
+    /// Qualified names which aren't keywords themselves are looked up
+    /// by their last component, so that they're still one token.
+    fn lookup_lexeme(&self, lexeme: &Lexeme) -> Option<&Token> {
+        self.token_dict.get(lexeme.text).or_else(|| {
+            if lexeme.kind == LexemeKind::QualifiedName {
+                lexeme
+                    .text
+                    .rsplit('.')
+                    .next()
+                    .and_then(|last| self.token_dict.get(last))
+            } else {
+                None
+            }
+        })
     }
     pub fn to_pickleable(self) -> PickleableTokenizer {
-        (
+        PickleableTokenizer(
             self.use_unknowns,
             self.num_reserved_tokens,
             self.unknown_token,
             self.token_dict,
+            self.version,
         )
     }
     pub fn from_pickleable(tup: PickleableTokenizer) -> Self {
@@ -158,6 +259,7 @@ impl Tokenizer {
             num_reserved_tokens: tup.1,
             unknown_token: tup.2,
             token_dict: tup.3,
+            version: tup.4,
         }
     }
     pub fn num_tokens(&self) -> i64 {
@@ -165,20 +267,63 @@ impl Tokenizer {
             as i64
     }
 }
+
 static SYMBOLS_REGEX: &'static str = r",|:=|:>|:|=>|<=|>=|=|<>|>|<[^-]|->|<-|@@|\+{1,2}|\*{1,2}|-|~|/\\|\\/|/|%|\^|\|=|&&|\|\||\)|\(|\|\}|\{\||@\{|\{|\}|;|\|)|\{\||\|\}|\[|\]";
-pub fn get_words(string: &str) -> Vec<&str> {
+/// The words version 1 tokenizers split sentences into. The regex
+/// doesn't say what kind of word it found, so each gets the kind the
+/// lexer would give it on its own.
+fn unversioned_words(sentence: &str) -> Vec<Lexeme<'_>> {
     lazy_static! {
         static ref WORDS: Regex =
-            Regex::new(&format!(r"({}|(\??([[:word:]'!#\.])+)", SYMBOLS_REGEX)).unwrap();
+            Regex::new(&format!(r"({}|\.|(\??([[:word:]'!#])+)", SYMBOLS_REGEX)).unwrap();
     }
-    WORDS.find_iter(string).map(|m| m.as_str()).collect()
+    WORDS
+        .find_iter(sentence)
+        .map(|m| {
+            let kind = match lex_code(m.as_str()).as_slice() {
+                [lexeme] => lexeme.kind,
+                _ => LexemeKind::Symbol,
+            };
+            Lexeme {
+                kind,
+                text: m.as_str(),
+                start: m.start(),
+                end: m.end(),
+            }
+        })
+        .collect()
 }
-pub fn get_symbols(string: &str) -> Vec<&str> {
-    lazy_static! {
-        static ref WORDS: Regex =
-            Regex::new(&format!(r"({}|\.|(\??([[:word:]'!#])+)", SYMBOLS_REGEX)).unwrap();
+
+/// The tokens of a sentence, along with its words and the alignment
+/// between the two.
+pub struct AlignedTokens<'a> {
+    pub words: Vec<Lexeme<'a>>,
+    pub tokens: Vec<Token>,
+    /// For each word, the position of its token, if it has one
+    pub word_to_token: Vec<Option<usize>>,
+    /// For each token, the position of the word it came from
+    pub token_to_word: Vec<usize>,
+}
+
+impl<'a> AlignedTokens<'a> {
+    /// The word that the token at a position came from.
+    pub fn token_word(&self, token_idx: usize) -> Option<&Lexeme<'a>> {
+        self.token_to_word
+            .get(token_idx)
+            .map(|word_idx| &self.words[*word_idx])
     }
-    WORDS.find_iter(string).map(|m| m.as_str()).collect()
+}
+
+/// The words of a term, which are its lexemes other than comments.
+pub fn get_words(string: &str) -> Vec<&str> {
+    lex_code(string).into_iter().map(|lexeme| lexeme.text).collect()
+}
+/// The words of get_words, as byte ranges into the string.
+pub fn get_word_ranges(string: &str) -> Vec<(usize, usize)> {
+    lex_code(string)
+        .into_iter()
+        .map(|lexeme| (lexeme.start, lexeme.end))
+        .collect()
 }
 
 pub fn normalize_sentence_length(
@@ -193,3 +338,21 @@ pub fn normalize_sentence_length(
     }
     tokenlist
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenizer_versions() {
+        let keywords = vec!["Nat".to_string(), ".".to_string(), "add".to_string()];
+        let tokenizer = Tokenizer::from_keywords(false, 2, keywords);
+        assert_eq!(tokenizer.tokenize("Nat.add x"), vec![4]);
+
+        let unversioned = (false, 2usize, 0i64, tokenizer.token_dict.clone());
+        let old = Tokenizer::from_bytes(&bincode::serialize(&unversioned).unwrap()).unwrap();
+        assert_eq!(old.tokenize("Nat.add x"), vec![2, 3, 4]);
+        let new = Tokenizer::from_bytes(&bincode::serialize(&tokenizer).unwrap()).unwrap();
+        assert_eq!(new.version, TOKENIZER_VERSION);
+    }
+}
@@ -1,5 +1,5 @@
 
-from typing import List, Optional, Tuple, Dict
+from typing import Callable, List, Optional, Tuple, Dict, Union
 from dataclasses import dataclass
 
 
@@ -16,11 +16,25 @@ class ProofContext:
     given_up_goals: List[Obligation]
 
 
+class Provenance:
+    filename: str
+    line_number: int
+    proof_index: int
+
+
+# ("tactic",), ("bullet", level), ("open_brace",), ("close_brace",),
+# ("goal_selector", goal_num, inner_kind), ("proof_start",), or
+# ("proof_end", "Qed" | "Defined" | "Admitted" | "Abort" | "Save")
+CommandKind = Tuple
+
+
 class ScrapedTactic:
     relevant_lemmas: List[str]
     prev_tactics: List[str]
     context: ProofContext
     tactic: str
+    provenance: Optional[Provenance]
+    command_kind: CommandKind
 
 
 @dataclass
@@ -37,12 +51,20 @@ class DataloaderArgs:
     max_premises: int
     num_keywords: int
     num_relevance_samples: int
+    max_arity: int
     keywords_file: Optional[str]
     context_filter: str
     save_embedding: Optional[str]
     save_features_state: Optional[str]
     load_embedding: Optional[str]
     load_features_state: Optional[str]
+    skip_bad_lines: bool
+    bad_lines_file: Optional[str]
+    dataset_cache_dir: Optional[str]
+    manifest_prelude: Optional[str]
+    manifest_split: Optional[str]
+    preprocess_config: Optional[str]
+    tactic_signatures: Optional[str]
 
 
 class ScrapedTransition:
@@ -51,6 +73,31 @@ class ScrapedTransition:
     before: ProofContext
     after: ProofContext
     tactic: str
+    provenance: Optional[Provenance]
+    linearized: Optional[List[str]]
+    partial: bool
+
+    def delta(self) -> ContextDelta:
+        ...
+
+
+class ContextDelta:
+    hyps_added: List[str]
+    hyps_removed: List[str]
+    hyps_modified: List[Tuple[str, str]]
+    focused_goal_changed: bool
+    goals_closed: List[Obligation]
+    goals_created: List[Obligation]
+    goals_shelved: List[Obligation]
+
+    def is_empty(self) -> bool:
+        ...
+
+
+class ScrapedProof:
+    statement: Optional[str]
+    tactics: List[ScrapedTactic]
+    closing_command: Optional[str]
 
 
 class Tokenizer:
@@ -69,16 +116,27 @@ class TokenMap:
 
 
 PickleableIndexer = Tuple[int, Dict[str, int], bool]
-PickleableTokenizer = Tuple[bool, int, int, Dict[str, int]]
+# Tokenizers pickled before the tokenizer version (the final int) was
+# recorded lack it, and keep the old tokenization.
+PickleableTokenizer = Union[Tuple[bool, int, int, Dict[str, int], int],
+                            Tuple[bool, int, int, Dict[str, int]]]
 PickleableFeaturesTokenMap = Tuple[Dict[str, int],
                                    Dict[str, int],
                                    Dict[str, int]]
 
 PickleableTokenMap = PickleableFeaturesTokenMap
 
+# Metadata pickled before the preprocessing config was recorded lacks
+# the final string, and is read with the default config.
 PickleableFPAMetadata = Tuple[PickleableIndexer,
                               PickleableTokenizer,
-                              PickleableFeaturesTokenMap]
+                              PickleableFeaturesTokenMap,
+                              str]
+
+# (premise index, or None for the goal; start word; end word)
+# Only encode_fpa_tactic produces these. features_polyarg_tensors has
+# no span targets, so FPA models never predict them.
+TermSpan = Tuple[Optional[int], int, int]
 
 
 def features_to_total_distances_tensors(args: DataloaderArgs,
@@ -102,6 +160,43 @@ def scraped_tactics_from_file(filename: str,
     ...
 
 
+def command_kind(command: str) -> CommandKind:
+    ...
+
+
+def context_delta(before: ProofContext, after: ProofContext) -> ContextDelta:
+    ...
+
+
+def scraped_proofs_from_file(args: DataloaderArgs, filename: str,
+                             num_proofs: Optional[int]) \
+                             -> List[ScrapedProof]:
+    ...
+
+
+# {"filter": str, "accepted": int, "rejected": int,
+#  "accepted_examples": List[str], "rejected_examples": List[str],
+#  "children": List[FilterExplanation]}
+FilterExplanation = Dict
+
+
+def explain_filter(args: DataloaderArgs, filename: str,
+                   num_examples: int) -> FilterExplanation:
+    ...
+
+
+def format_filter_explanation(explanation: FilterExplanation) -> str:
+    ...
+
+
+# Makes the predicate available to context filters as py:name
+# If a predicate raises, whatever applied the filter raises its error.
+def register_filter_predicate(name: str,
+                              predicate: Callable[[ScrapedTactic], bool]) \
+                              -> None:
+    ...
+
+
 def features_polyarg_tensors(args: DataloaderArgs, filename: str) \
     -> Tuple[PickleableFPAMetadata,
              Tuple[
@@ -113,7 +208,7 @@ def features_polyarg_tensors(args: DataloaderArgs, filename: str) \
                  List[List[int]],
                  List[List[float]],
                  List[int],
-                 List[int]],
+                 List[List[int]]],
              Tuple[List[int], int]]:
     ...
 
@@ -130,7 +225,7 @@ def features_polyarg_tensors_with_meta(args: DataloaderArgs, filename: str,
               List[List[int]],
               List[List[float]],
               List[int],
-              List[int]],
+              List[List[int]]],
           Tuple[List[int], int]]:
     ...
 
@@ -174,6 +269,26 @@ def decode_fpa_result(args: DataloaderArgs, metadata: PickleableFPAMetadata,
     ...
 
 
+def decode_fpa_multiarg_result(args: DataloaderArgs,
+                               metadata: PickleableFPAMetadata,
+                               hyps: List[str], goal: str, tac_idx: int,
+                               arg_idxs: List[int],
+                               arg_spans: List[Optional[TermSpan]]) -> str:
+    ...
+
+
+def encode_fpa_tactic(args: DataloaderArgs, metadata: PickleableFPAMetadata,
+                      hyps: List[str], goal: str,
+                      tactic: str) \
+                      -> Tuple[int, List[int], List[Optional[TermSpan]]]:
+    ...
+
+
+def preprocess_fpa_tactic(metadata: PickleableFPAMetadata, goal: str,
+                          tactic: str) -> str:
+    ...
+
+
 def features_vocab_sizes(tmap: TokenMap) -> Tuple[List[int], int]:
     ...
 
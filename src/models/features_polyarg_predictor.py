@@ -794,7 +794,8 @@ class FeaturesPolyargPredictor(
                        torch.LongTensor(word_features),
                        torch.FloatTensor(vec_features),
                        torch.LongTensor(tactic_stem_indices),
-                       torch.LongTensor(arg_indices)]
+                       # max_arity is 1, so this is the only column
+                       torch.LongTensor(arg_indices)[:, 0]]
             with open("tensors.pickle", 'wb') as f:
                 torch.save(tensors, f)
             eprint(tensors, guard=arg_values.print_tensors)
@@ -977,6 +978,9 @@ def extract_dataloader_args(args: argparse.Namespace) -> DataloaderArgs:
     dargs.max_string_distance = args.max_string_distance
     dargs.max_premises = args.max_premises
     dargs.num_relevance_samples = args.num_relevance_samples
+    # The model predicts a single argument, so tactics with more are
+    # left unrecognized rather than partially encoded.
+    dargs.max_arity = 1
     assert args.load_tokens, \
         "Must have a keywords file for the rust dataloader"
     dargs.keywords_file = args.load_tokens